@@ -0,0 +1,253 @@
+//! Frame timing and pointer velocity, for map animations driven from async tasks.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use dioxus::prelude::*;
+use web_time::Instant;
+
+use crate::_const::{MAX_Z, MIN_Z, REF_Z};
+use crate::geometry::nearest_world_copy;
+use crate::url_state::MapState;
+
+/// ~60 fps
+pub const FRAME_TIME: Duration = Duration::from_millis(16);
+
+/// Waits for the next animation frame and returns the time since `last`, in seconds.
+pub async fn next_frame(last: &mut Instant) -> f64 {
+    async_std::task::sleep(FRAME_TIME).await;
+    let now = Instant::now();
+    let dt = (now - *last).as_secs_f64();
+    *last = now;
+    dt
+}
+
+/// only samples this recent count towards the velocity
+const VELOCITY_WINDOW: Duration = Duration::from_millis(100);
+
+/// Pointer positions of the last [`VELOCITY_WINDOW`], to get the speed of a drag at release.
+#[derive(Default, Clone, Debug)]
+pub struct VelocityTracker {
+    samples: VecDeque<(Instant, (f64, f64))>,
+}
+
+impl VelocityTracker {
+    pub fn push(&mut self, pos: (f64, f64)) {
+        let now = Instant::now();
+        self.samples.push_back((now, pos));
+        while self
+            .samples
+            .front()
+            .is_some_and(|(t, _)| now - *t > VELOCITY_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Pixels per second; zero if the pointer stood still for the whole window.
+    pub fn velocity(&self) -> (f64, f64) {
+        let now = Instant::now();
+        let recent = self
+            .samples
+            .iter()
+            .filter(|(t, _)| now - *t <= VELOCITY_WINDOW)
+            .collect::<Vec<_>>();
+        let (Some(first), Some(last)) = (recent.first(), recent.last()) else {
+            return (0.0, 0.0);
+        };
+        let dt = (last.0 - first.0).as_secs_f64();
+        if dt < 0.001 {
+            return (0.0, 0.0);
+        }
+        ((last.1 .0 - first.1 .0) / dt, (last.1 .1 - first.1 .1) / dt)
+    }
+}
+
+/// Curvature of the fly-to path: how far it zooms out on the way. `sqrt(2)` is the value
+/// van Wijk and Nuij found most pleasant, and the one d3 uses.
+const FLY_RHO: f64 = std::f64::consts::SQRT_2;
+
+/// Animates `map_state` to `target` along a van Wijk & Nuij "smooth and efficient zooming
+/// and panning" path: zoom out, pan, zoom back in, in `duration`.
+///
+/// `flight` holds the running task; it is `Some` while the map flies, so that other code
+/// can hold off on work until the map stops, and a new flight or a user press can cancel it.
+/// The layers of `target` are switched to right away; the bearing turns the short way
+/// round along with the flight.
+pub fn fly_to(
+    mut map_state: Signal<MapState>,
+    mut flight: Signal<Option<Task>>,
+    target: MapState,
+    duration: Duration,
+) {
+    if let Some(task) = flight.take() {
+        task.cancel();
+    }
+    let start = map_state.peek().clone();
+    map_state.set(MapState {
+        pos: start.pos,
+        zoom: start.zoom,
+        bearing: start.bearing,
+        ..target.clone()
+    });
+    // fly to the copy of the target nearest to us, so we never go the long way round the world
+    let target_pos = nearest_world_copy(start.pos, target.pos);
+    let path = FlyPath::new((start.pos, start.zoom), (target_pos, target.zoom));
+    let turn = (target.bearing - start.bearing + 180.0).rem_euclid(360.0) - 180.0;
+
+    let task = spawn(async move {
+        let started = Instant::now();
+        let mut last_frame = started;
+        loop {
+            next_frame(&mut last_frame).await;
+            let t = (started.elapsed().as_secs_f64() / duration.as_secs_f64()).min(1.0);
+            if t >= 1.0 {
+                break;
+            }
+            let t = ease_in_out(t);
+            let (pos, zoom) = path.at(t);
+            let mut state = map_state.write();
+            state.pos = pos;
+            state.zoom = zoom;
+            state.bearing = start.bearing + turn * t;
+        }
+        map_state.set(target);
+        flight.set(None);
+    });
+    flight.set(Some(task));
+}
+
+/// Slow at both ends, so the flight does not start or stop with a jerk.
+fn ease_in_out(t: f64) -> f64 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// The path in `(center, view width)` space, where the view width is the world size of
+/// the smaller screen side: two tiles at the current zoom.
+struct FlyPath {
+    from: (f64, f64),
+    delta: (f64, f64),
+    /// straight distance between the centers
+    dist: f64,
+    width_from: f64,
+    r_from: f64,
+    /// length of the path, in units of `FLY_RHO`
+    length: f64,
+}
+
+impl FlyPath {
+    fn new(from: ((f64, f64), f64), to: ((f64, f64), f64)) -> Self {
+        let width = |zoom: f64| 2.0 * f64::exp2(REF_Z - zoom);
+        let (width_from, width_to) = (width(from.1), width(to.1));
+        let delta = (to.0 .0 - from.0 .0, to.0 .1 - from.0 .1);
+        let dist = delta.0.hypot(delta.1);
+        let rho2 = FLY_RHO * FLY_RHO;
+        let (r_from, length) = if dist < 1e-9 {
+            (0.0, (width_to / width_from).ln() / FLY_RHO)
+        } else {
+            let b = |w: f64, sign: f64| {
+                (width_to * width_to - width_from * width_from + sign * rho2 * rho2 * dist * dist)
+                    / (2.0 * w * rho2 * dist)
+            };
+            let r = |b: f64| (f64::sqrt(b * b + 1.0) - b).ln();
+            let r_from = r(b(width_from, 1.0));
+            let r_to = r(b(width_to, -1.0));
+            (r_from, (r_to - r_from) / FLY_RHO)
+        };
+        Self {
+            from: from.0,
+            delta,
+            dist,
+            width_from,
+            r_from,
+            length,
+        }
+    }
+
+    /// `(pos, zoom)` at `t` in `0..=1`.
+    fn at(&self, t: f64) -> ((f64, f64), f64) {
+        let s = t * self.length;
+        let (u, width) = if self.dist < 1e-9 {
+            (t, self.width_from * f64::exp(FLY_RHO * s))
+        } else {
+            let rho2 = FLY_RHO * FLY_RHO;
+            let cosh_r0 = self.r_from.cosh();
+            let u = self.width_from / (rho2 * self.dist)
+                * (cosh_r0 * (FLY_RHO * s + self.r_from).tanh() - self.r_from.sinh());
+            (
+                u,
+                self.width_from * cosh_r0 / (FLY_RHO * s + self.r_from).cosh(),
+            )
+        };
+        let pos = (
+            self.from.0 + u * self.delta.0,
+            self.from.1 + u * self.delta.1,
+        );
+        let zoom = REF_Z - (width / 2.0).log2();
+        (pos, zoom.clamp(MIN_Z as f64, MAX_Z as f64))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64, eps: f64) {
+        assert!((a - b).abs() <= eps, "{a} != {b} (eps {eps})");
+    }
+
+    #[test]
+    fn fly_path_ends_at_start_and_target() {
+        let (from, to) = (((150_000.0, 95_000.0), 14.0), ((40_000.0, 120_000.0), 11.5));
+        let path = FlyPath::new(from, to);
+        // the hyperbolic functions leave a few 1e-5 of a tile; `fly_to` lands on the
+        // exact target after the last frame anyway
+        let eps = 1e-3;
+        let (pos, zoom) = path.at(0.0);
+        assert_close(pos.0, from.0 .0, eps);
+        assert_close(pos.1, from.0 .1, eps);
+        assert_close(zoom, from.1, 1e-9);
+        let (pos, zoom) = path.at(1.0);
+        assert_close(pos.0, to.0 .0, eps);
+        assert_close(pos.1, to.0 .1, eps);
+        assert_close(zoom, to.1, 1e-9);
+    }
+
+    #[test]
+    fn long_flights_zoom_out_on_the_way() {
+        let path = FlyPath::new(((10_000.0, 90_000.0), 15.0), ((200_000.0, 90_000.0), 15.0));
+        let (pos, zoom) = path.at(0.5);
+        // far enough out to see both ends at once
+        assert!(zoom < 10.0, "{zoom}");
+        assert_close(pos.0, 105_000.0, 1e-3);
+        // and the center moves forward the whole way
+        let mut last_x = f64::NEG_INFINITY;
+        for i in 0..=20 {
+            let ((x, _), _) = path.at(i as f64 / 20.0);
+            assert!(x > last_x);
+            last_x = x;
+        }
+    }
+
+    #[test]
+    fn zoom_without_moving() {
+        let pos = (150_000.0, 95_000.0);
+        let path = FlyPath::new((pos, 5.0), (pos, 12.0));
+        let mut last_zoom = 0.0;
+        for i in 0..=10 {
+            let (p, zoom) = path.at(i as f64 / 10.0);
+            assert!(p.0.is_finite() && p.1.is_finite() && zoom.is_finite());
+            assert_eq!(p, pos);
+            assert!(zoom > last_zoom);
+            last_zoom = zoom;
+        }
+        assert_close(last_zoom, 12.0, 1e-9);
+
+        // nothing to do at all
+        let (p, zoom) = FlyPath::new((pos, 9.0), (pos, 9.0)).at(0.5);
+        assert_eq!((p, zoom), (pos, 9.0));
+    }
+}
@@ -10,13 +10,24 @@
 //! ```sh
 //! dx serve --platform web --features web --example hash_fragment_state --features=ciborium,base64 -- --no-default-features
 //! ```
+use client::animation::fly_to;
 use client::index_db::init_db_globals;
+use client::markers::{add_marker, use_markers};
+use client::measure::{add_measure_point, Measurement};
+use client::tile_provider::init_tile_providers;
 use client::url_state::{MapState, INIT_STATE};
 #[allow(non_snake_case)]
-use client::{comp::MapsDisplay, input::MapsController};
+use client::{
+    comp::{load_vector_file, MapsDisplay},
+    input::MapsController,
+    overlay::{Feature, VectorLayer},
+};
 use dioxus::prelude::*;
 use dioxus_logger::tracing::{error, info, warn};
 
+/// how long the map takes to fly to a new url hash
+const FLY_TO_DURATION: std::time::Duration = std::time::Duration::from_millis(1500);
+
 fn main() {
     dioxus_logger::init(dioxus_logger::tracing::Level::INFO).expect("failed to init logger");
     info!("dioxus launch...");
@@ -24,6 +35,7 @@ fn main() {
         info!("init db globals...");
         init_db_globals();
         info!("init db globals: done.");
+        init_tile_providers();
         rsx! {
             Router::<Route> {}
         }
@@ -45,6 +57,21 @@ fn Home(url_hash: ReadOnlySignal<MapState>) -> Element {
     let mut _init_state = url_hash.read().clone();
     let mut map_state = use_signal(|| _init_state.clone());
     let dimensions: Signal<(f64, f64)> = use_signal(|| (0.0, 0.0));
+    let device_pixel_ratio = use_signal(|| 1.0);
+    // a device setting, so it is kept in local storage and not in the url
+    let tile_quality = dioxus_sdk::storage::use_synced_storage::<
+        dioxus_sdk::storage::LocalStorage,
+        f64,
+    >("tile_quality".to_string(), || 1.0);
+    let cursor_pos: Signal<Option<(f64, f64)>> = use_signal(|| None);
+    let markers = use_markers();
+    let vector_layers = use_signal(Vec::<VectorLayer>::new);
+    let selected_feature = use_signal(|| None::<Feature>);
+    // the running fly-to animation, if any
+    let flight = use_signal(|| None::<Task>);
+    let animating = use_memo(move || flight.read().is_some());
+    // `Some` while the measure tool is on
+    let measurement = use_signal(|| None::<Measurement>);
     // if url is invalid, they will not match
     if !_init_state.is_init {
         warn!("redirecting from invalid url_hash into default...");
@@ -54,10 +81,20 @@ fn Home(url_hash: ReadOnlySignal<MapState>) -> Element {
         });
     }
 
-    // Change the state signal when the url hash changes -- on a debounce
+    // Fly to the url hash when it changes -- by navigation, or by hand.
+    // The hash is rounded, so compare the way it is written, not the exact numbers.
     use_effect(move || {
-        if *map_state.peek() != *url_hash.read() {
-            map_state.set(url_hash());
+        let target = url_hash();
+        if flight.peek().is_some() {
+            return;
+        }
+        if map_state.peek().to_string() != target.to_string() {
+            if map_state.peek().is_init {
+                fly_to(map_state, flight, target, FLY_TO_DURATION);
+            } else {
+                // nothing sensible to fly from
+                map_state.set(target);
+            }
         }
     });
 
@@ -66,15 +103,38 @@ fn Home(url_hash: ReadOnlySignal<MapState>) -> Element {
         dioxus_sdk::utils::timing::use_debounce(std::time::Duration::from_millis(100), move |_| {
             navigator().replace(Route::Home { url_hash: map_state() });
         });
+    // ...but not with the frames in between while flying.
     use_effect(move || {
-        if *map_state.read() != *url_hash.peek() {
+        if *animating.read() {
+            return;
+        }
+        if map_state.read().to_string() != url_hash.peek().to_string() {
             debounce_write_url.action(());
         }
     });
 
-
     rsx! {
-        MapsController { map_state, dimensions }
-        MapsDisplay { map_state, dimensions }
+        MapsController {
+            map_state,
+            dimensions,
+            device_pixel_ratio,
+            cursor_pos,
+            flight,
+            on_long_press: move |world| add_marker(markers, world),
+            on_click: move |world| add_measure_point(measurement, world),
+            on_file_drop: move |(name, bytes)| load_vector_file(vector_layers, name, bytes),
+        }
+        MapsDisplay {
+            map_state,
+            dimensions,
+            cursor_pos,
+            markers,
+            vector_layers,
+            selected_feature,
+            measurement,
+            animating,
+            device_pixel_ratio,
+            tile_quality,
+        }
     }
 }
@@ -1,44 +1,122 @@
-use crate::{_const::REF_Z, url_state::MapState};
+use crate::{
+    _const::{MAX_Z, MIN_Z, REF_Z},
+    coord_format::CoordFormat,
+    data_loader::TileKey,
+    geometry::{
+        lat_lon_to_world, meters_per_pixel, nearest_world_copy, screen_to_world,
+        visible_world_bounds, world_to_lat_lon, world_to_screen, TILE_PX,
+    },
+    markers::{remove_marker, update_marker, Marker, MARKER_ICONS},
+    measure::{format_area, format_length, Measurement},
+    gpx::Gpx,
+    overlay::{parse_vector_file, Feature, FeatureStyle, Geometry, VectorLayer},
+    tile_protocol::TileError,
+    tile_provider::TileProviderInfo,
+    url_state::{MapState, OverlayLayer},
+};
 #[allow(non_snake_case)]
 use dioxus::prelude::*;
-use dioxus_logger::tracing::info;
-use std::collections::HashMap;
+use dioxus_logger::tracing::{info, warn};
+use std::{borrow::Cow, collections::HashMap, rc::Rc};
 
 #[component]
 pub fn MapsDisplay(
-    map_state: ReadOnlySignal<MapState>,
+    map_state: Signal<MapState>,
     dimensions: ReadOnlySignal<(f64, f64)>,
+    cursor_pos: ReadOnlySignal<Option<(f64, f64)>>,
+    markers: Signal<Vec<Marker>>,
+    vector_layers: Signal<Vec<VectorLayer>>,
+    selected_feature: Signal<Option<Feature>>,
+    measurement: Signal<Option<Measurement>>,
+    /// set while `animation::fly_to` moves the map
+    animating: ReadOnlySignal<bool>,
+    device_pixel_ratio: ReadOnlySignal<f64>,
+    /// multiplies `device_pixel_ratio`; below 1 saves data, above 1 sharpens
+    tile_quality: Signal<f64>,
 ) -> Element {
+    let providers = crate::tile_provider::use_tile_providers();
+    // tile levels are picked for the base layer's images; overlays load the same tiles
+    let tile_px = use_memo(move || {
+        let base_layer = map_state.read().base_layer.clone();
+        match &*providers.read() {
+            Some(Ok(providers)) => providers
+                .iter()
+                .find(|p| p.id == base_layer)
+                .map_or(TILE_PX, |p| p.tile_px as f64),
+            _ => TILE_PX,
+        }
+    });
     let squares_in_view = use_memo(move || {
-        crate::geometry::get_tile_positions(map_state.read().pos, map_state.read().zoom, *dimensions.read())
+        let state = map_state.read();
+        crate::geometry::get_tile_positions(
+            state.pos,
+            state.zoom,
+            state.bearing,
+            *dimensions.read(),
+            *tile_px.read(),
+            *device_pixel_ratio.read() * *tile_quality.read(),
+        )
     });
-    let map_tile_is_loaded = use_signal(HashMap::<(i32, i32, i32), bool>::new);
-    let map_tile_data = use_signal(HashMap::<(i32, i32, i32), String>::new);
+    let bearing = use_memo(move || map_state.read().bearing);
+    let layers = use_memo(move || map_state.read().layers());
+    let layer_ids = use_memo(move || layers.read().iter().map(|l| l.0.clone()).collect());
+    let map_tile_is_loaded = use_signal(HashMap::<TileKey, bool>::new);
+    let map_tile_data = use_signal(HashMap::<TileKey, String>::new);
+    let map_tile_errors = use_signal(HashMap::<TileKey, TileError>::new);
 
     crate::data_loader::use_handle_data_loading(
+        layer_ids.into(),
         squares_in_view.into(),
+        animating,
         map_tile_is_loaded,
         map_tile_data,
+        map_tile_errors,
     );
 
     rsx! {
         MapsCrosshair {}
-        MapsInterface {map_state},
-
-        ul {
-            id: "main_display_list",
-            style: "list-style-type:none;margin:0;padding:0;",
-
-            for (sq_z , sq_x , sq_y) in squares_in_view.read().iter().cloned() {
-                li { key: "tile_li_{sq_z}_{sq_x}_{sq_y}",
-                    MapsTile {
-                        map_state,
-                        dimensions,
-                        sq_x,
-                        sq_y,
-                        sq_z,
-                        map_tile_is_loaded,
-                        map_tile_data,
+        MapsScaleBar { map_state, dimensions }
+        MapsCompass { map_state }
+        MapsMarkers { map_state, dimensions, markers }
+        MapsInterface {map_state, dimensions, cursor_pos, vector_layers, selected_feature, measurement, tile_quality},
+        MapsVectorOverlay { map_state, dimensions, vector_layers, selected_feature }
+        MapsMeasureOverlay { map_state, dimensions, measurement }
+
+        // tiles and images are laid out north-up, then the whole lot is turned around the screen center
+        div {
+            id: "maps_rotated",
+            style: "
+                position: absolute;
+                left: 0;
+                top: 0;
+                width: 100vw;
+                height: 100vh;
+                z-index: -1;
+                transform: rotate({-bearing()}deg);
+            ",
+            MapsGroundOverlays { map_state, dimensions, vector_layers }
+
+            for (layer_id, opacity) in layers.read().iter().cloned() {
+                ul {
+                    key: "layer_ul_{layer_id}",
+                    class: "maps_layer",
+                    style: "list-style-type:none;margin:0;padding:0;opacity:{opacity};",
+
+                    for ((sq_z , sq_x , sq_y), world_copy) in squares_in_view.read().iter().map(|sq| crate::geometry::wrap_tile(*sq)) {
+                        li { key: "tile_li_{layer_id}_{sq_z}_{sq_x}_{sq_y}_{world_copy}",
+                            MapsTile {
+                                map_state,
+                                dimensions,
+                                layer_id: layer_id.clone(),
+                                sq_x,
+                                sq_y,
+                                sq_z,
+                                world_copy,
+                                map_tile_is_loaded,
+                                map_tile_data,
+                                map_tile_errors,
+                            }
+                        }
                     }
                 }
             }
@@ -47,9 +125,18 @@ pub fn MapsDisplay(
 }
 
 #[component]
-fn MapsInterface(map_state: ReadOnlySignal<MapState>) -> Element {
+fn MapsInterface(
+    map_state: Signal<MapState>,
+    dimensions: ReadOnlySignal<(f64, f64)>,
+    cursor_pos: ReadOnlySignal<Option<(f64, f64)>>,
+    vector_layers: Signal<Vec<VectorLayer>>,
+    selected_feature: Signal<Option<Feature>>,
+    measurement: Signal<Option<Measurement>>,
+    tile_quality: Signal<f64>,
+) -> Element {
     rsx! {
         div {
+            id: "maps_interface",
             style: "
                 position: absolute; 
                 left: 2vmin; 
@@ -57,12 +144,374 @@ fn MapsInterface(map_state: ReadOnlySignal<MapState>) -> Element {
                 width: 20vmin; 
                 height: 92vh;
                 background-color: white;
-                z-index: 0;
+                z-index: 6667;
+                overflow-y: auto;
                 padding: 1vmin;
                 margin: 1vmin;
             ",
             
-            h3 { "zoom = {map_state.read().zoom:?} pos = {map_state.read().pos:?}" }
+            h3 { "zoom {map_state.read().zoom:.2}" }
+            MapsCoordReadout { map_state, dimensions, cursor_pos }
+            MapsLayerPicker { map_state }
+            MapsTileQuality { tile_quality }
+            MapsVectorFiles { vector_layers, selected_feature }
+            MapsMeasurePanel { measurement }
+        }
+    }
+}
+
+/// Hands `text` to the browser as a file download.
+fn download_text_file(file_name: &str, mime: &str, text: &str) {
+    let js = format!(
+        "const blob = new Blob([{}], {{type: {}}});
+        const a = document.createElement('a');
+        a.href = URL.createObjectURL(blob);
+        a.download = {};
+        a.click();
+        URL.revokeObjectURL(a.href);",
+        serde_json::to_string(text).unwrap_or_default(),
+        serde_json::to_string(mime).unwrap_or_default(),
+        serde_json::to_string(file_name).unwrap_or_default(),
+    );
+    document::eval(&js);
+}
+
+/// Parses a dropped or picked file and adds it as a vector layer.
+pub fn load_vector_file(mut vector_layers: Signal<Vec<VectorLayer>>, name: String, bytes: Vec<u8>) {
+    match parse_vector_file(&name, &bytes) {
+        Ok(layer) => {
+            info!("loaded {} features from {name:?}", layer.features.len());
+            vector_layers.write().push(layer);
+        }
+        Err(e) => warn!("failed to load vector file {name:?}: {e}"),
+    }
+}
+
+#[component]
+fn MapsVectorFiles(
+    mut vector_layers: Signal<Vec<VectorLayer>>,
+    mut selected_feature: Signal<Option<Feature>>,
+) -> Element {
+    let names = vector_layers
+        .read()
+        .iter()
+        .map(|l| (l.name.clone(), l.features.len()))
+        .collect::<Vec<_>>();
+    rsx! {
+        h4 { "files" }
+        input {
+            r#type: "file",
+            accept: ".geojson,.json,.gpx,.kml,.kmz",
+            multiple: true,
+            onchange: move |event: Event<FormData>| async move {
+                let Some(engine) = event.files() else {
+                    return;
+                };
+                for name in engine.files() {
+                    match engine.read_file(&name).await {
+                        Some(bytes) => load_vector_file(vector_layers, name, bytes),
+                        None => warn!("failed to read file {name:?}"),
+                    }
+                }
+            },
+        }
+        for (i, (name, count)) in names.into_iter().enumerate() {
+            p { key: "vector_file_{i}_{name}", class: "maps_vector_file",
+                "{name} ({count}) "
+                button {
+                    title: "download as GPX",
+                    onclick: move |_| {
+                        let Some(layer) = vector_layers.peek().get(i).cloned() else {
+                            return;
+                        };
+                        let text = Gpx::from_vector_layer(&layer).to_gpx_string();
+                        let file_name = match layer.name.rsplit_once('.') {
+                            Some((stem, _)) => format!("{stem}.gpx"),
+                            None => format!("{}.gpx", layer.name),
+                        };
+                        download_text_file(&file_name, "application/gpx+xml", &text);
+                    },
+                    "gpx"
+                }
+                button {
+                    onclick: move |_| {
+                        if i < vector_layers.peek().len() {
+                            vector_layers.write().remove(i);
+                        }
+                        selected_feature.set(None);
+                    },
+                    "remove"
+                }
+            }
+        }
+        if let Some(feature) = selected_feature.read().clone() {
+            h4 { "selected feature" }
+            table { class: "maps_feature_properties",
+                for (key, value) in feature.properties {
+                    tr { key: "{key}",
+                        td { "{key}" }
+                        td { "{value}" }
+                    }
+                }
+            }
+            button { onclick: move |_| selected_feature.set(None), "close" }
+        }
+    }
+}
+
+/// Starts and stops measure mode, and lists the measured lengths and area.
+#[component]
+fn MapsMeasurePanel(mut measurement: Signal<Option<Measurement>>) -> Element {
+    let Some(current) = measurement.read().clone() else {
+        return rsx! {
+            h4 { "measure" }
+            button { onclick: move |_| measurement.set(Some(Measurement::default())), "start" }
+        };
+    };
+    let segments = current
+        .segment_lengths_m()
+        .into_iter()
+        .map(format_length)
+        .collect::<Vec<_>>();
+    let total = format_length(current.total_length_m());
+    let area = current.area_m2().map(format_area);
+
+    rsx! {
+        h4 { "measure" }
+        p { "click the map to add points" }
+        label {
+            input {
+                r#type: "checkbox",
+                checked: current.closed,
+                onchange: move |event: Event<FormData>| {
+                    if let Some(m) = measurement.write().as_mut() {
+                        m.closed = event.checked();
+                    }
+                },
+            }
+            " polygon"
+        }
+        ol { class: "maps_measure_segments",
+            for (i, length) in segments.into_iter().enumerate() {
+                li { key: "{i}", "{length}" }
+            }
+        }
+        p { "total: {total}" }
+        if let Some(area) = area {
+            p { "area: {area}" }
+        }
+        button {
+            onclick: move |_| {
+                if let Some(m) = measurement.write().as_mut() {
+                    m.points.pop();
+                }
+            },
+            "undo"
+        }
+        button {
+            onclick: move |_| {
+                if let Some(m) = measurement.write().as_mut() {
+                    m.points.clear();
+                }
+            },
+            "clear"
+        }
+        button {
+            title: "download as GPX route",
+            disabled: current.points.len() < 2,
+            onclick: move |_| {
+                let Some(m) = measurement.peek().clone() else {
+                    return;
+                };
+                let text = Gpx::from_measurement(&m, "measurement").to_gpx_string();
+                download_text_file("measurement.gpx", "application/gpx+xml", &text);
+            },
+            "gpx"
+        }
+        button { onclick: move |_| measurement.set(None), "done" }
+    }
+}
+
+#[component]
+fn MapsCoordReadout(
+    map_state: ReadOnlySignal<MapState>,
+    dimensions: ReadOnlySignal<(f64, f64)>,
+    cursor_pos: ReadOnlySignal<Option<(f64, f64)>>,
+) -> Element {
+    let mut coord_format = use_signal(|| CoordFormat::Dd);
+    let center = world_to_lat_lon(map_state.read().pos);
+    let cursor = cursor_pos.read().map(|px| {
+        let state = map_state.read();
+        world_to_lat_lon(screen_to_world(state.pos, state.zoom, state.bearing, *dimensions.read(), px))
+    });
+    let center_text = coord_format.read().format(center);
+    let cursor_text = cursor.map(|c| coord_format.read().format(c));
+
+    rsx! {
+        select {
+            value: "{coord_format.read().name()}",
+            onchange: move |event: Event<FormData>| {
+                if let Some(f) = CoordFormat::from_name(&event.value()) {
+                    coord_format.set(f);
+                }
+            },
+            for f in CoordFormat::ALL {
+                option { value: "{f.name()}", selected: *coord_format.read() == f, "{f.name()}" }
+            }
+        }
+        MapsCoordLine { label: "center", text: center_text }
+        if let Some(text) = cursor_text {
+            MapsCoordLine { label: "cursor", text }
+        }
+    }
+}
+
+#[component]
+fn MapsCoordLine(label: String, text: String) -> Element {
+    let mut copied = use_signal(|| false);
+    let copy_text = text.clone();
+    rsx! {
+        p { class: "maps_coord",
+            "{label}: {text} "
+            button {
+                title: "copy to clipboard",
+                onclick: move |_| {
+                    let js = format!(
+                        "navigator.clipboard.writeText({});",
+                        serde_json::to_string(&copy_text).unwrap_or_default(),
+                    );
+                    spawn(async move {
+                        if let Err(e) = document::eval(&js).await {
+                            info!("copy to clipboard failed: {e:?}");
+                            return;
+                        }
+                        copied.set(true);
+                        async_std::task::sleep(std::time::Duration::from_millis(1000)).await;
+                        copied.set(false);
+                    });
+                },
+                if *copied.read() { "copied" } else { "copy" }
+            }
+        }
+    }
+}
+
+#[component]
+fn MapsLayerPicker(map_state: Signal<MapState>) -> Element {
+    let providers = crate::tile_provider::use_tile_providers();
+    let providers = match &*providers.read() {
+        Some(Ok(providers)) => providers.clone(),
+        Some(Err(e)) => return rsx! { p { "failed to load layers: {e}" } },
+        None => return rsx! { p { "loading layers..." } },
+    };
+    let attribution = providers
+        .iter()
+        .filter(|p| map_state.read().layers().iter().any(|l| l.0 == p.id))
+        .map(|p| p.attribution.clone())
+        .filter(|a| !a.is_empty())
+        .collect::<Vec<_>>()
+        .join(" | ");
+
+    rsx! {
+        h4 { "base layer" }
+        for provider in providers.iter().cloned() {
+            MapsBaseLayerRow { key: "base_layer_{provider.id}", map_state, provider: provider.clone() }
+        }
+        h4 { "overlays" }
+        for provider in providers.iter().cloned() {
+            if map_state.read().base_layer != provider.id.as_str() {
+                MapsOverlayRow { key: "overlay_{provider.id}", map_state, provider: provider.clone() }
+            }
+        }
+        p { class: "maps_attribution", "{attribution}" }
+    }
+}
+
+/// `(multiplier of the device pixel ratio, label)` offered for the tile quality
+const TILE_QUALITIES: &[(f64, &str)] = &[(0.5, "data saver"), (1.0, "screen"), (1.5, "extra sharp")];
+
+#[component]
+fn MapsTileQuality(mut tile_quality: Signal<f64>) -> Element {
+    let current = *tile_quality.read();
+    rsx! {
+        h4 { "tile quality" }
+        select {
+            onchange: move |event: Event<FormData>| {
+                if let Ok(quality) = event.value().parse::<f64>() {
+                    tile_quality.set(quality);
+                }
+            },
+            for (quality, label) in TILE_QUALITIES.iter().copied() {
+                option { value: "{quality}", selected: current == quality, "{label}" }
+            }
+        }
+    }
+}
+
+#[component]
+fn MapsBaseLayerRow(mut map_state: Signal<MapState>, provider: TileProviderInfo) -> Element {
+    let is_base = map_state.read().base_layer == provider.id.as_str();
+    let id = provider.id.clone();
+    rsx! {
+        label { display: "block",
+            input {
+                r#type: "radio",
+                name: "base_layer",
+                checked: is_base,
+                onchange: move |_| {
+                    let mut state = map_state.write();
+                    state.overlays.retain(|o| o.id != id);
+                    state.base_layer = Cow::Owned(id.clone());
+                },
+            }
+            "{provider.name}"
+        }
+    }
+}
+
+#[component]
+fn MapsOverlayRow(mut map_state: Signal<MapState>, provider: TileProviderInfo) -> Element {
+    let opacity = map_state
+        .read()
+        .overlays
+        .iter()
+        .find(|o| o.id == provider.id)
+        .map(|o| o.opacity);
+    let toggle_id = provider.id.clone();
+    let opacity_id = provider.id.clone();
+    rsx! {
+        label { display: "block",
+            input {
+                r#type: "checkbox",
+                checked: opacity.is_some(),
+                onchange: move |event: Event<FormData>| {
+                    let mut state = map_state.write();
+                    state.overlays.retain(|o| o.id != toggle_id);
+                    if event.checked() {
+                        state.overlays.push(OverlayLayer {
+                            id: toggle_id.clone(),
+                            opacity: 0.5,
+                        });
+                    }
+                },
+            }
+            "{provider.name}"
+        }
+        if let Some(opacity) = opacity {
+            input {
+                r#type: "range",
+                min: "0",
+                max: "100",
+                value: "{(opacity * 100.0).round()}",
+                oninput: move |event: Event<FormData>| {
+                    if let Ok(value) = event.value().parse::<f64>() {
+                        let mut state = map_state.write();
+                        if let Some(o) = state.overlays.iter_mut().find(|o| o.id == opacity_id) {
+                            o.opacity = (value / 100.0).clamp(0.0, 1.0);
+                        }
+                    }
+                },
+            }
         }
     }
 }
@@ -71,25 +520,36 @@ fn MapsInterface(map_state: ReadOnlySignal<MapState>) -> Element {
 fn MapsTile(
     map_state: ReadOnlySignal<MapState>,
     dimensions: ReadOnlySignal<(f64, f64)>,
+    layer_id: String,
     sq_x: i32,
     sq_y: i32,
     sq_z: i32,
+    /// which repetition of the world east (positive) or west of the antimeridian to draw in
+    world_copy: i32,
     // src: ReadOnlySignal<String>,
-    map_tile_is_loaded: ReadOnlySignal<HashMap<(i32, i32, i32), bool>>,
-    map_tile_data: ReadOnlySignal<HashMap<(i32, i32, i32), String>>,
+    map_tile_is_loaded: ReadOnlySignal<HashMap<TileKey, bool>>,
+    map_tile_data: ReadOnlySignal<HashMap<TileKey, String>>,
+    map_tile_errors: ReadOnlySignal<HashMap<TileKey, TileError>>,
 ) -> Element {
     let pos = map_state.read().pos;
     let zoom = map_state.read().zoom;
     let tile_size_abs = f64::exp2(REF_Z - sq_z as f64);
-    let tile_pos_abs = (sq_x as f64 * tile_size_abs, sq_y as f64 * tile_size_abs);
+    let tile_pos_abs = (
+        sq_x as f64 * tile_size_abs + world_copy as f64 * crate::geometry::world_size(),
+        sq_y as f64 * tile_size_abs,
+    );
     let tile_relative = (tile_pos_abs.0 - pos.0, tile_pos_abs.1 - pos.1);
     let camera_zoom = f64::exp2(REF_Z - zoom);
     let tile_camera = (tile_relative.0 / camera_zoom, tile_relative.1 / camera_zoom);
     let tile_size = tile_size_abs / camera_zoom;
     let z_index = sq_z - 32;
 
+    let key = (layer_id.clone(), sq_z, sq_x, sq_y);
+    let data_key = key.clone();
+    let error_key = key.clone();
+    let fallback_key = key.clone();
     let is_loaded = use_memo(move || {
-        if let Some(x) = map_tile_is_loaded.read().get(&(sq_z, sq_x, sq_y)) {
+        if let Some(x) = map_tile_is_loaded.read().get(&key) {
             *x
         } else {
             false
@@ -99,18 +559,103 @@ fn MapsTile(
         if *is_loaded.read() {
             map_tile_data
                 .peek()
-                .get(&(sq_z, sq_x, sq_y))
+                .get(&data_key)
                 .cloned()
                 .unwrap_or("".to_string())
         } else {
             "".to_string()
         }
     });
+    // while this tile loads, show a zoomed-in piece of its closest loaded ancestor, and
+    // its loaded descendants on top of that, so zooming either way never leaves holes
+    let fallback_keys = use_memo(move || {
+        if *is_loaded.read() {
+            return (None, vec![]);
+        }
+        let loaded = map_tile_is_loaded.read();
+        (
+            closest_loaded_ancestor(&loaded, &fallback_key),
+            loaded_descendants(&loaded, &fallback_key, FALLBACK_DESCENDANT_LEVELS),
+        )
+    });
+    // (id, left %, top %, size %, src) inside this tile's box, the ancestor first; only
+    // redone when the fallback tiles change, not on every tile that loads anywhere
+    let fallback = use_memo(move || {
+        let data = map_tile_data.peek();
+        let src = |k: &TileKey| data.get(k).cloned().unwrap_or_default();
+        let (ancestor, descendants) = &*fallback_keys.read();
+        // the ancestor is 2^dz times our size, shifted so that our quadrant is in view
+        let ancestor = ancestor.iter().map(|a_key| {
+            let dz = sq_z - a_key.1;
+            let scale = f64::exp2(dz as f64) * 100.0;
+            let off_x = (sq_x - (a_key.2 << dz)) as f64;
+            let off_y = (sq_y - (a_key.3 << dz)) as f64;
+            let id = format!("{}_{}_{}", a_key.1, a_key.2, a_key.3);
+            (id, -off_x * 100.0, -off_y * 100.0, scale, src(a_key))
+        });
+        let descendants = descendants.iter().map(|d_key| {
+            let dz = d_key.1 - sq_z;
+            let scale = f64::exp2(-dz as f64) * 100.0;
+            let off_x = (d_key.2 - (sq_x << dz)) as f64;
+            let off_y = (d_key.3 - (sq_y << dz)) as f64;
+            let id = format!("{}_{}_{}", d_key.1, d_key.2, d_key.3);
+            (id, off_x * scale, off_y * scale, scale, src(d_key))
+        });
+        ancestor.chain(descendants).collect::<Vec<_>>()
+    });
+    // only errors that will not go away on retry get a placeholder
+    let no_imagery = use_memo(move || {
+        map_tile_errors
+            .read()
+            .get(&error_key)
+            .is_some_and(|e| !e.is_retryable())
+    });
 
     rsx! {
+        if !fallback.read().is_empty() {
+            div {
+                id: "tile_fallback_{layer_id}_{sq_z}_{sq_x}_{sq_y}_{world_copy}",
+                style: "
+                    width: {tile_size*50.0}vmin;
+                    height: {tile_size*50.0}vmin;
+                    position: absolute;
+                    left: calc({tile_camera.0*50.0}vmin + 50vw);
+                    top: calc({tile_camera.1*50.0}vmin + 50vh);
+                    overflow: hidden;
+                    z-index: {z_index};
+                ",
+                for (id, left, top, size, src) in fallback.read().iter().cloned() {
+                    img {
+                        key: "{id}",
+                        style: "
+                            position: absolute;
+                            width: {size}%;
+                            height: {size}%;
+                            left: {left}%;
+                            top: {top}%;
+                        ",
+                        src,
+                    }
+                }
+            }
+        }
+        if *no_imagery.read() && !*is_loaded.read() {
+            div {
+                id: "tile_missing_{layer_id}_{sq_z}_{sq_x}_{sq_y}_{world_copy}",
+                class: "tile_no_imagery",
+                style: "
+                    width: {tile_size*50.0}vmin;
+                    height: {tile_size*50.0}vmin;
+                    position: absolute;
+                    left: calc({tile_camera.0*50.0}vmin + 50vw);
+                    top: calc({tile_camera.1*50.0}vmin + 50vh);
+                    z-index: {z_index};
+                ",
+            }
+        }
         if *is_loaded.read() {
             img {
-                id: "tile_img_{sq_z}_{sq_x}_{sq_y}",
+                id: "tile_img_{layer_id}_{sq_z}_{sq_x}_{sq_y}_{world_copy}",
                 style: "
                     width: {tile_size*50.0}vmin;
                     height: {tile_size*50.0}vmin; 
@@ -127,6 +672,40 @@ fn MapsTile(
     }
 }
 
+/// how many zoom levels down to look for loaded tiles when zooming out
+const FALLBACK_DESCENDANT_LEVELS: i32 = 2;
+
+fn closest_loaded_ancestor(loaded: &HashMap<TileKey, bool>, key: &TileKey) -> Option<TileKey> {
+    let (layer_id, sq_z, sq_x, sq_y) = key;
+    (1..=(sq_z - MIN_Z)).find_map(|levels| {
+        let (a_z, a_x, a_y) = crate::geometry::tile_ancestor((*sq_z, *sq_x, *sq_y), levels);
+        let a_key = (layer_id.clone(), a_z, a_x, a_y);
+        loaded.get(&a_key).copied().unwrap_or(false).then_some(a_key)
+    })
+}
+
+/// Loaded tiles below `key`, taking the shallowest loaded one for each quadrant.
+fn loaded_descendants(
+    loaded: &HashMap<TileKey, bool>,
+    key: &TileKey,
+    levels: i32,
+) -> Vec<TileKey> {
+    let (layer_id, sq_z, sq_x, sq_y) = key;
+    if levels == 0 || *sq_z >= MAX_Z {
+        return vec![];
+    }
+    let mut found = vec![];
+    for (c_z, c_x, c_y) in crate::geometry::tile_children((*sq_z, *sq_x, *sq_y)) {
+        let c_key = (layer_id.clone(), c_z, c_x, c_y);
+        if loaded.get(&c_key).copied().unwrap_or(false) {
+            found.push(c_key);
+        } else {
+            found.append(&mut loaded_descendants(loaded, &c_key, levels - 1));
+        }
+    }
+    found
+}
+
 #[component]
 pub fn MapsCrosshair() -> Element {
     rsx! {
@@ -159,3 +738,456 @@ pub fn MapsCrosshair() -> Element {
         }
     }
 }
+
+/// Needle pointing north; a click turns the map back north-up.
+#[component]
+pub fn MapsCompass(map_state: Signal<MapState>) -> Element {
+    let bearing = map_state.read().bearing;
+    rsx! {
+        button {
+            id: "maps_compass",
+            title: "reset to north",
+            onclick: move |_| map_state.write().bearing = 0.0,
+            div { class: "maps_compass_needle", style: "transform: rotate({-bearing}deg);", "▲" }
+        }
+    }
+}
+
+/// longest the scale bar gets, in `vmin`
+const SCALE_BAR_MAX_VMIN: f64 = 20.0;
+const METERS_PER_FOOT: f64 = 0.3048;
+const FEET_PER_MILE: f64 = 5280.0;
+
+/// Largest 1, 2 or 5 × 10^n that is not over `max`.
+fn nice_round_length(max: f64) -> f64 {
+    let pow10 = f64::powf(10.0, max.log10().floor());
+    [5.0, 2.0, 1.0]
+        .into_iter()
+        .map(|m| m * pow10)
+        .find(|len| *len <= max)
+        .unwrap_or(pow10)
+}
+
+/// `(bar width in vmin, label)` for the longest round length that fits.
+fn scale_bar_step(max_units: f64, vmin_per_unit: f64, unit: &str) -> (f64, String) {
+    let len = nice_round_length(max_units);
+    (len * vmin_per_unit, format!("{len} {unit}"))
+}
+
+#[component]
+pub fn MapsScaleBar(
+    map_state: ReadOnlySignal<MapState>,
+    dimensions: ReadOnlySignal<(f64, f64)>,
+) -> Element {
+    let (lat, _) = world_to_lat_lon(map_state.read().pos);
+    let zoom = map_state.read().zoom;
+    // one tile at `zoom` is drawn 50vmin wide, see `MapsTile`
+    let meters_per_vmin = meters_per_pixel(lat, zoom) * TILE_PX / 50.0;
+    if !meters_per_vmin.is_finite() || meters_per_vmin <= 0.0 || dimensions.read().0 <= 0.0 {
+        return rsx! {};
+    }
+    let max_m = SCALE_BAR_MAX_VMIN * meters_per_vmin;
+    let metric = if max_m >= 1000.0 {
+        scale_bar_step(max_m / 1000.0, 1000.0 / meters_per_vmin, "km")
+    } else {
+        scale_bar_step(max_m, 1.0 / meters_per_vmin, "m")
+    };
+    let max_ft = max_m / METERS_PER_FOOT;
+    let imperial = if max_ft >= FEET_PER_MILE {
+        scale_bar_step(
+            max_ft / FEET_PER_MILE,
+            FEET_PER_MILE * METERS_PER_FOOT / meters_per_vmin,
+            "mi",
+        )
+    } else {
+        scale_bar_step(max_ft, METERS_PER_FOOT / meters_per_vmin, "ft")
+    };
+
+    rsx! {
+        div {
+            id: "maps_scale_bar",
+            style: "
+                position: absolute;
+                right: 2vmin;
+                bottom: 2vmin;
+                z-index: 6667;
+                pointer-events: none;
+                font: 1.6vmin sans-serif;
+                color: black;
+            ",
+            div { class: "maps_scale_bar_metric", style: "width: {metric.0}vmin;", "{metric.1}" }
+            div { class: "maps_scale_bar_imperial", style: "width: {imperial.0}vmin;", "{imperial.1}" }
+        }
+    }
+}
+
+#[component]
+fn MapsMarkers(
+    map_state: ReadOnlySignal<MapState>,
+    dimensions: ReadOnlySignal<(f64, f64)>,
+    markers: Signal<Vec<Marker>>,
+) -> Element {
+    let mut open_marker = use_signal(|| None::<String>);
+    let (pos, zoom, bearing) = (map_state.read().pos, map_state.read().zoom, map_state.read().bearing);
+    let dims = *dimensions.read();
+    let on_screen = markers
+        .read()
+        .iter()
+        .map(|m| {
+            let world = nearest_world_copy(pos, lat_lon_to_world(m.lat_lon));
+            let screen = world_to_screen(pos, zoom, bearing, dims, world);
+            (m.clone(), screen)
+        })
+        .filter(|(_, (x, y))| *x >= 0.0 && *y >= 0.0 && *x <= dims.0 && *y <= dims.1)
+        .collect::<Vec<_>>();
+
+    rsx! {
+        for (marker, (x, y)) in on_screen {
+            div {
+                key: "marker_{marker.id}",
+                class: "maps_marker",
+                style: "left: {x}px; top: {y}px;",
+                div {
+                    class: "maps_marker_icon",
+                    title: "{marker.label}",
+                    onclick: {
+                        let id = marker.id.clone();
+                        move |_| {
+                            let is_open = open_marker.peek().as_deref() == Some(id.as_str());
+                            open_marker.set(if is_open { None } else { Some(id.clone()) });
+                        }
+                    },
+                    "{marker.icon}"
+                }
+                div { class: "maps_marker_label", "{marker.label}" }
+                if open_marker.read().as_deref() == Some(marker.id.as_str()) {
+                    MapsMarkerPopup { markers, marker: marker.clone(), open_marker }
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn MapsMarkerPopup(
+    markers: Signal<Vec<Marker>>,
+    marker: Marker,
+    open_marker: Signal<Option<String>>,
+) -> Element {
+    let label_marker = marker.clone();
+    let icon_marker = marker.clone();
+    let popup_marker = marker.clone();
+    let delete_id = marker.id.clone();
+    rsx! {
+        div { class: "maps_marker_popup",
+            input {
+                r#type: "text",
+                value: "{marker.label}",
+                onchange: move |event: Event<FormData>| {
+                    update_marker(markers, Marker { label: event.value(), ..label_marker.clone() });
+                },
+            }
+            select {
+                value: "{marker.icon}",
+                onchange: move |event: Event<FormData>| {
+                    update_marker(markers, Marker { icon: event.value(), ..icon_marker.clone() });
+                },
+                for icon in MARKER_ICONS.iter() {
+                    option { value: "{icon}", selected: marker.icon == *icon, "{icon}" }
+                }
+            }
+            textarea {
+                value: "{marker.popup}",
+                placeholder: "notes",
+                onchange: move |event: Event<FormData>| {
+                    update_marker(markers, Marker { popup: event.value(), ..popup_marker.clone() });
+                },
+            }
+            p { "{CoordFormat::Dd.format(marker.lat_lon)}" }
+            button {
+                onclick: move |_| {
+                    open_marker.set(None);
+                    remove_marker(markers, delete_id.clone());
+                },
+                "delete"
+            }
+        }
+    }
+}
+
+/// SVG path data for a line, or for a closed ring when `close` is set.
+fn svg_path(points: &[(f64, f64)], close: bool) -> String {
+    let mut d = String::new();
+    for (i, (x, y)) in points.iter().enumerate() {
+        let cmd = if i == 0 { 'M' } else { 'L' };
+        d.push_str(&format!("{cmd}{x:.1},{y:.1} "));
+    }
+    if close && !points.is_empty() {
+        d.push('Z');
+    }
+    d
+}
+
+/// World rings of one geometry and whether it is a polygon; a line string is a single open ring.
+type WorldPath = (Vec<Vec<(f64, f64)>>, bool);
+
+/// A vector feature projected to world coordinates once, when the layers change.
+#[derive(Clone, PartialEq)]
+struct WorldFeature {
+    key: String,
+    feature: Rc<Feature>,
+    /// world bounding box, `(min, max)`
+    bbox: ((f64, f64), (f64, f64)),
+    paths: Vec<WorldPath>,
+    points: Vec<(f64, f64)>,
+}
+
+fn project_vector_layers(vector_layers: &[VectorLayer]) -> Vec<WorldFeature> {
+    let mut features = vec![];
+    for (layer_i, layer) in vector_layers.iter().enumerate() {
+        for (feature_i, feature) in layer.features.iter().enumerate() {
+            let project =
+                |ring: &[(f64, f64)]| ring.iter().map(|p| lat_lon_to_world(*p)).collect::<Vec<_>>();
+            let mut paths = vec![];
+            let mut points = vec![];
+            for geometry in feature.geometries.iter() {
+                match geometry {
+                    Geometry::Point(p) => points.push(lat_lon_to_world(*p)),
+                    Geometry::LineString(line) => paths.push((vec![project(line)], false)),
+                    Geometry::Polygon(rings) => {
+                        paths.push((rings.iter().map(|ring| project(ring)).collect::<Vec<_>>(), true))
+                    }
+                }
+            }
+            let (mut min, mut max) = ((f64::INFINITY, f64::INFINITY), (f64::NEG_INFINITY, f64::NEG_INFINITY));
+            let vertices = paths.iter().flat_map(|(rings, _)| rings.iter().flatten()).chain(points.iter());
+            for (x, y) in vertices {
+                min = (min.0.min(*x), min.1.min(*y));
+                max = (max.0.max(*x), max.1.max(*y));
+            }
+            features.push(WorldFeature {
+                key: format!("{layer_i}_{feature_i}"),
+                feature: Rc::new(feature.clone()),
+                bbox: (min, max),
+                paths,
+                points,
+            });
+        }
+    }
+    features
+}
+
+/// Draws every loaded vector layer. The world coordinates are kept between frames; each
+/// frame only moves the features that touch the screen into screen pixels.
+#[component]
+fn MapsVectorOverlay(
+    map_state: ReadOnlySignal<MapState>,
+    dimensions: ReadOnlySignal<(f64, f64)>,
+    vector_layers: ReadOnlySignal<Vec<VectorLayer>>,
+    selected_feature: Signal<Option<Feature>>,
+) -> Element {
+    let world_features = use_memo(move || project_vector_layers(&vector_layers.read()));
+    let (pos, zoom, bearing) = (map_state.read().pos, map_state.read().zoom, map_state.read().bearing);
+    let dims = *dimensions.read();
+    // room for the point circles and thick strokes at the screen edge
+    let (view_min, view_max) = visible_world_bounds(pos, zoom, bearing, dims, 16.0);
+
+    // (key, feature, [(svg path, is polygon)], [point centers])
+    let mut shapes = vec![];
+    for world_feature in world_features.read().iter() {
+        let (min, max) = world_feature.bbox;
+        // the whole feature moves to the world copy its center is nearest in
+        let center_x = (min.0 + max.0) / 2.0;
+        let shift = nearest_world_copy(pos, (center_x, 0.0)).0 - center_x;
+        let (min, max) = ((min.0 + shift, min.1), (max.0 + shift, max.1));
+        let project =
+            |world: &(f64, f64)| world_to_screen(pos, zoom, bearing, dims, (world.0 + shift, world.1));
+        if max.0 < view_min.0 || min.0 > view_max.0 || max.1 < view_min.1 || min.1 > view_max.1 {
+            continue;
+        }
+        let paths = world_feature
+            .paths
+            .iter()
+            .map(|(rings, is_polygon)| {
+                let d = rings
+                    .iter()
+                    .map(|ring| {
+                        svg_path(&ring.iter().map(project).collect::<Vec<_>>(), *is_polygon)
+                    })
+                    .collect::<String>();
+                (d, *is_polygon)
+            })
+            .collect::<Vec<_>>();
+        let points = world_feature.points.iter().map(project).collect::<Vec<_>>();
+        shapes.push((world_feature.key.clone(), world_feature.feature.clone(), paths, points));
+    }
+
+    rsx! {
+        svg {
+            id: "maps_vector_overlay",
+            width: "{dims.0}",
+            height: "{dims.1}",
+            style: "position: absolute; left: 0; top: 0; z-index: 6667; pointer-events: none;",
+            for (key, feature, paths, points) in shapes {
+                MapsVectorFeature { key: "{key}", feature, paths, points, selected_feature }
+            }
+        }
+    }
+}
+
+/// KML `GroundOverlay` images, above the tiles and under everything else.
+#[component]
+fn MapsGroundOverlays(
+    map_state: ReadOnlySignal<MapState>,
+    dimensions: ReadOnlySignal<(f64, f64)>,
+    vector_layers: ReadOnlySignal<Vec<VectorLayer>>,
+) -> Element {
+    let (pos, zoom) = (map_state.read().pos, map_state.read().zoom);
+    let dims = *dimensions.read();
+    // drawn inside the turned `maps_rotated` box, like the tiles, so laid out north-up
+    let project = move |world: (f64, f64)| world_to_screen(pos, zoom, 0.0, dims, world);
+
+    // (key, src, left px, top px, width px, height px, rotation)
+    let mut images = vec![];
+    for (layer_i, layer) in vector_layers.read().iter().enumerate() {
+        for (overlay_i, overlay) in layer.ground_overlays.iter().enumerate() {
+            let bounds = overlay.bounds;
+            // boxes crossing the antimeridian have east < west
+            let east = if bounds.east < bounds.west { bounds.east + 360.0 } else { bounds.east };
+            let north_west = lat_lon_to_world((bounds.north, bounds.west));
+            let south_east = lat_lon_to_world((bounds.south, east));
+            let center_x = (north_west.0 + south_east.0) / 2.0;
+            let shift = nearest_world_copy(pos, (center_x, 0.0)).0 - center_x;
+            let north_west = project((north_west.0 + shift, north_west.1));
+            let south_east = project((south_east.0 + shift, south_east.1));
+            images.push((
+                format!("{layer_i}_{overlay_i}"),
+                overlay.image_src.clone(),
+                north_west.0,
+                north_west.1,
+                south_east.0 - north_west.0,
+                south_east.1 - north_west.1,
+                -overlay.rotation_deg,
+            ));
+        }
+    }
+
+    rsx! {
+        for (key, src, left, top, width, height, rotation) in images {
+            img {
+                key: "{key}",
+                class: "maps_ground_overlay",
+                style: "
+                    position: absolute;
+                    left: {left}px;
+                    top: {top}px;
+                    width: {width}px;
+                    height: {height}px;
+                    transform: rotate({rotation}deg);
+                    z-index: -1;
+                    pointer-events: none;
+                ",
+                src,
+            }
+        }
+    }
+}
+
+/// The measured line or polygon, with a dot on each clicked point.
+#[component]
+fn MapsMeasureOverlay(
+    map_state: ReadOnlySignal<MapState>,
+    dimensions: ReadOnlySignal<(f64, f64)>,
+    measurement: ReadOnlySignal<Option<Measurement>>,
+) -> Element {
+    let Some(current) = measurement.read().clone() else {
+        return rsx! {};
+    };
+    let (pos, zoom, bearing) = (map_state.read().pos, map_state.read().zoom, map_state.read().bearing);
+    let dims = *dimensions.read();
+    // each point goes next to the one before it, so segments crossing the antimeridian
+    // take the short way, and the first one next to the camera
+    let mut last = pos;
+    let points = current
+        .points
+        .iter()
+        .map(|p| {
+            last = nearest_world_copy(last, lat_lon_to_world(*p));
+            world_to_screen(pos, zoom, bearing, dims, last)
+        })
+        .collect::<Vec<_>>();
+    let d = svg_path(&points, current.closed);
+    let fill = if current.closed { "rgba(255, 136, 0, 0.2)" } else { "none" };
+
+    rsx! {
+        svg {
+            id: "maps_measure_overlay",
+            width: "{dims.0}",
+            height: "{dims.1}",
+            style: "position: absolute; left: 0; top: 0; z-index: 6667; pointer-events: none;",
+            path {
+                d: "{d}",
+                style: "stroke: #ff8800; stroke-width: 3; stroke-dasharray: 8 4; fill: {fill};",
+            }
+            for (i, (x, y)) in points.into_iter().enumerate() {
+                circle {
+                    key: "{i}",
+                    cx: "{x}",
+                    cy: "{y}",
+                    r: "5",
+                    style: "fill: white; stroke: #ff8800; stroke-width: 2;",
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn MapsVectorFeature(
+    feature: Rc<Feature>,
+    paths: Vec<(String, bool)>,
+    points: Vec<(f64, f64)>,
+    mut selected_feature: Signal<Option<Feature>>,
+) -> Element {
+    let FeatureStyle {
+        stroke,
+        stroke_width,
+        stroke_opacity,
+        fill,
+        fill_opacity,
+        marker_color,
+    } = feature.style.clone();
+    let stroke_style =
+        format!("stroke: {stroke}; stroke-width: {stroke_width}; stroke-opacity: {stroke_opacity};");
+    let polygon_style =
+        format!("{stroke_style} fill: {fill}; fill-opacity: {fill_opacity}; fill-rule: evenodd;");
+    let line_style =
+        format!("{stroke_style} fill: none; stroke-linecap: round; stroke-linejoin: round;");
+    let paths = paths
+        .into_iter()
+        .map(|(d, is_polygon)| {
+            let style = if is_polygon { &polygon_style } else { &line_style };
+            (d, style.clone())
+        })
+        .collect::<Vec<_>>();
+    rsx! {
+        g {
+            style: "pointer-events: visiblePainted; cursor: pointer;",
+            onclick: move |_| selected_feature.set(Some((*feature).clone())),
+            for (d, style) in paths {
+                path {
+                    d: "{d}",
+                    style: "{style}",
+                }
+            }
+            for (x, y) in points {
+                circle {
+                    cx: "{x}",
+                    cy: "{y}",
+                    r: "6",
+                    style: "fill: {marker_color}; stroke: white; stroke-width: 2;",
+                }
+            }
+        }
+    }
+}
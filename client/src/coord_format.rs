@@ -0,0 +1,232 @@
+//! Text forms of a WGS84 position: decimal degrees, degrees/minutes/seconds and UTM.
+
+/// How the interface writes coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoordFormat {
+    /// decimal degrees, `44.4268000, 26.1025000`
+    Dd,
+    /// degrees, minutes, seconds, `44°25'36.5"N 26°06'09.0"E`
+    Dms,
+    /// Universal Transverse Mercator, `35T 428561E 4919670N`
+    Utm,
+}
+
+impl CoordFormat {
+    pub const ALL: [CoordFormat; 3] = [CoordFormat::Dd, CoordFormat::Dms, CoordFormat::Utm];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Dd => "DD",
+            Self::Dms => "DMS",
+            Self::Utm => "UTM",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    pub fn format(&self, lat_lon: (f64, f64)) -> String {
+        let (lat, lon) = lat_lon;
+        let lon = (lon + 180.0).rem_euclid(360.0) - 180.0;
+        match self {
+            Self::Dd => format!("{lat:.7}, {lon:.7}"),
+            Self::Dms => format!(
+                "{} {}",
+                format_dms(lat, ['N', 'S']),
+                format_dms(lon, ['E', 'W'])
+            ),
+            Self::Utm => match lat_lon_to_utm((lat, lon)) {
+                Some(utm) => format!(
+                    "{}{} {:.0}E {:.0}N",
+                    utm.zone, utm.band, utm.easting, utm.northing
+                ),
+                None => "outside UTM (polar)".to_string(),
+            },
+        }
+    }
+}
+
+fn format_dms(value: f64, hemispheres: [char; 2]) -> String {
+    let hemisphere = if value >= 0.0 {
+        hemispheres[0]
+    } else {
+        hemispheres[1]
+    };
+    // round once, on the tenths of a second, so we never print 60.0"
+    let tenths = (value.abs() * 36_000.0).round() as u64;
+    let degrees = tenths / 36_000;
+    let minutes = tenths % 36_000 / 600;
+    let seconds = (tenths % 600) as f64 / 10.0;
+    format!("{degrees}°{minutes:02}'{seconds:04.1}\"{hemisphere}")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UtmCoord {
+    pub zone: u8,
+    pub band: char,
+    pub easting: f64,
+    pub northing: f64,
+}
+
+/// UTM latitude bands, 8° each from 80°S, the last one (X) stretched to 84°N.
+const UTM_BANDS: &[u8] = b"CDEFGHJKLMNPQRSTUVWX";
+
+/// WGS84 to UTM with the usual series expansion (Snyder, "Map Projections - A Working
+/// Manual", p. 61), good to about a millimeter inside the zone. `None` near the poles,
+/// where UPS is used instead.
+pub fn lat_lon_to_utm(lat_lon: (f64, f64)) -> Option<UtmCoord> {
+    let (lat, lon) = lat_lon;
+    if !(-80.0..=84.0).contains(&lat) {
+        return None;
+    }
+    let band = UTM_BANDS[(((lat + 80.0) / 8.0).floor() as usize).min(UTM_BANDS.len() - 1)] as char;
+
+    let mut zone = ((lon + 180.0) / 6.0).floor() as i32 + 1;
+    zone = zone.clamp(1, 60);
+    // south-west Norway and Svalbard have irregular zones
+    if band == 'V' && (3.0..12.0).contains(&lon) {
+        zone = 32;
+    }
+    if band == 'X' && (0.0..42.0).contains(&lon) {
+        zone = match lon {
+            lon if lon < 9.0 => 31,
+            lon if lon < 21.0 => 33,
+            lon if lon < 33.0 => 35,
+            _ => 37,
+        };
+    }
+
+    const A: f64 = 6_378_137.0;
+    const F: f64 = 1.0 / 298.257_223_563;
+    const K0: f64 = 0.9996;
+    let e2 = F * (2.0 - F);
+    let e4 = e2 * e2;
+    let e6 = e4 * e2;
+    let ep2 = e2 / (1.0 - e2);
+
+    let phi = lat.to_radians();
+    let lon0 = ((zone - 1) * 6 - 180 + 3) as f64;
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let n = A / (1.0 - e2 * sin_phi * sin_phi).sqrt();
+    let t = phi.tan().powi(2);
+    let c = ep2 * cos_phi * cos_phi;
+    let a = cos_phi * (lon - lon0).to_radians();
+    let m = A
+        * ((1.0 - e2 / 4.0 - 3.0 * e4 / 64.0 - 5.0 * e6 / 256.0) * phi
+            - (3.0 * e2 / 8.0 + 3.0 * e4 / 32.0 + 45.0 * e6 / 1024.0) * (2.0 * phi).sin()
+            + (15.0 * e4 / 256.0 + 45.0 * e6 / 1024.0) * (4.0 * phi).sin()
+            - (35.0 * e6 / 3072.0) * (6.0 * phi).sin());
+
+    let easting = K0
+        * n
+        * (a + (1.0 - t + c) * a.powi(3) / 6.0
+            + (5.0 - 18.0 * t + t * t + 72.0 * c - 58.0 * ep2) * a.powi(5) / 120.0)
+        + 500_000.0;
+    let mut northing = K0
+        * (m + n
+            * phi.tan()
+            * (a * a / 2.0
+                + (5.0 - t + 9.0 * c + 4.0 * c * c) * a.powi(4) / 24.0
+                + (61.0 - 58.0 * t + t * t + 600.0 * c - 330.0 * ep2) * a.powi(6) / 720.0));
+    if lat < 0.0 {
+        northing += 10_000_000.0;
+    }
+    Some(UtmCoord {
+        zone: zone as u8,
+        band,
+        easting,
+        northing,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(lat: f64, lon: f64) -> String {
+        let utm = lat_lon_to_utm((lat, lon)).unwrap();
+        format!("{}{}", utm.zone, utm.band)
+    }
+
+    #[test]
+    fn bucharest() {
+        assert_eq!(
+            CoordFormat::Utm.format((44.4268, 26.1025)),
+            "35T 428561E 4919670N"
+        );
+        assert_eq!(
+            CoordFormat::Dms.format((44.4268, 26.1025)),
+            "44°25'36.5\"N 26°06'09.0\"E"
+        );
+        assert_eq!(
+            CoordFormat::Dd.format((44.4268, 26.1025)),
+            "44.4268000, 26.1025000"
+        );
+    }
+
+    #[test]
+    fn central_meridian_and_hemispheres() {
+        let utm = lat_lon_to_utm((0.0, 3.0)).unwrap();
+        assert_eq!((utm.zone, utm.band), (31, 'N'));
+        assert!((utm.easting - 500_000.0).abs() < 1e-6);
+        assert!(utm.northing.abs() < 1e-6);
+
+        // mirrored across the equator and across the central meridian
+        let north = lat_lon_to_utm((10.0, 25.0)).unwrap();
+        let south = lat_lon_to_utm((-10.0, 25.0)).unwrap();
+        let west = lat_lon_to_utm((10.0, 29.0)).unwrap();
+        assert_eq!((south.zone, south.band), (35, 'L'));
+        assert!((north.northing + south.northing - 10_000_000.0).abs() < 1e-6);
+        assert!((north.easting - south.easting).abs() < 1e-6);
+        assert!((north.easting + west.easting - 1_000_000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn norway_and_svalbard_zones() {
+        assert_eq!(zone(60.0, 5.0), "32V");
+        assert_eq!(zone(60.0, 2.0), "31V");
+        assert_eq!(zone(60.0, 12.5), "33V");
+        assert_eq!(zone(55.0, 5.0), "31U");
+        assert_eq!(zone(78.0, 8.0), "31X");
+        assert_eq!(zone(78.0, 20.0), "33X");
+        assert_eq!(zone(78.0, 30.0), "35X");
+        assert_eq!(zone(78.0, 40.0), "37X");
+        assert_eq!(zone(78.0, 45.0), "38X");
+        assert_eq!(zone(84.0, 0.0), "31X");
+    }
+
+    #[test]
+    fn poles_are_outside_utm() {
+        assert!(lat_lon_to_utm((84.1, 0.0)).is_none());
+        assert!(lat_lon_to_utm((-80.1, 0.0)).is_none());
+        assert_eq!(CoordFormat::Utm.format((89.0, 0.0)), "outside UTM (polar)");
+    }
+
+    #[test]
+    fn dms_rounding_carries() {
+        assert_eq!(format_dms(59.99999, ['N', 'S']), "60°00'00.0\"N");
+        assert_eq!(format_dms(10.999_999, ['E', 'W']), "11°00'00.0\"E");
+        assert_eq!(format_dms(-0.5, ['N', 'S']), "0°30'00.0\"S");
+        assert_eq!(
+            format_dms(12.0 + 34.0 / 60.0 + 56.78 / 3600.0, ['E', 'W']),
+            "12°34'56.8\"E"
+        );
+    }
+
+    #[test]
+    fn longitude_is_wrapped() {
+        assert_eq!(
+            CoordFormat::Dd.format((0.0, 190.0)),
+            "0.0000000, -170.0000000"
+        );
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for format in CoordFormat::ALL {
+            assert_eq!(CoordFormat::from_name(format.name()), Some(format));
+        }
+        assert_eq!(CoordFormat::from_name("MGRS"), None);
+    }
+}
@@ -4,21 +4,78 @@ use dioxus_logger::tracing::{error, info, warn};
 use std::collections::HashMap;
 
 use crate::index_db::{read_image, write_image};
-use base64::Engine;
+#[cfg(feature = "server")]
+use crate::tile_protocol::TileFrame;
+use crate::tile_protocol::{FrameDecoder, FrameError, TileError, TileMsg};
+use crate::tile_provider::TileImage;
+#[cfg(feature = "server")]
+use crate::tile_provider::{HttpTileSource, TileProvider, TileSource};
+
+/// how long to stop asking when upstream rate limits us without a `Retry-After`
+const DEFAULT_RATE_LIMIT_WAIT_SECS: u64 = 10;
+/// upper bound on an upstream `Retry-After`, so a bogus one cannot stall a layer for hours
+const MAX_RETRY_AFTER_SECS: u64 = 300;
+/// the server waits out a `Retry-After` up to this long itself, longer ones go to the client
+#[cfg(feature = "server")]
+const MAX_SERVER_RETRY_AFTER_SECS: u64 = 5;
+
+/// `(layer id, z, x, y)` - one tile of one layer.
+pub type TileKey = (String, i32, i32, i32);
+
+pub fn tile_keys(layer_ids: &[String], squares: &[(i32, i32, i32)]) -> Vec<TileKey> {
+    // world copies share one tile
+    let mut squares = squares
+        .iter()
+        .map(|sq| crate::geometry::wrap_tile(*sq).0)
+        .collect::<Vec<_>>();
+    squares.sort();
+    squares.dedup();
+    layer_ids
+        .iter()
+        .flat_map(|layer| {
+            squares
+                .iter()
+                .map(move |(sq_z, sq_x, sq_y)| (layer.clone(), *sq_z, *sq_x, *sq_y))
+        })
+        .collect()
+}
+
+/// Splits keys into one `(z, x, y)` request list per layer, keeping layer order.
+fn group_by_layer(keys: &[TileKey]) -> Vec<(String, Vec<(i32, i32, i32)>)> {
+    let mut groups: Vec<(String, Vec<(i32, i32, i32)>)> = vec![];
+    for (layer, sq_z, sq_x, sq_y) in keys.iter().cloned() {
+        match groups.iter_mut().find(|(l, _)| *l == layer) {
+            Some((_, list)) => list.push((sq_z, sq_x, sq_y)),
+            None => groups.push((layer, vec![(sq_z, sq_x, sq_y)])),
+        }
+    }
+    groups
+}
 
 pub(crate) fn use_handle_data_loading(
+    layer_ids: ReadOnlySignal<Vec<String>>,
     squares_to_load: ReadOnlySignal<Vec<(i32, i32, i32)>>,
-    mut map_tile_is_loaded: Signal<HashMap<(i32, i32, i32), bool>>,
-    mut map_tile_data: Signal<HashMap<(i32, i32, i32), String>>,
+    // while the map flies, every frame would restart the load; wait until it lands
+    animating: ReadOnlySignal<bool>,
+    mut map_tile_is_loaded: Signal<HashMap<TileKey, bool>>,
+    mut map_tile_data: Signal<HashMap<TileKey, String>>,
+    mut map_tile_errors: Signal<HashMap<TileKey, TileError>>,
 ) {
+    // set when the server reports an upstream rate limit; loading waits until then
+    let rate_limited_until = use_signal(|| None::<web_time::Instant>);
+
     // debounce the squares changing, so we debounce the whole load process
-    let mut squares_in_view = use_signal(Vec::<(i32, i32, i32)>::new);
+    let mut squares_in_view = use_signal(Vec::<TileKey>::new);
     let mut debounce_update_squares =
         dioxus_sdk::utils::timing::use_debounce(std::time::Duration::from_millis(100), move |_| {
-            squares_in_view.set(squares_to_load.peek().clone());
+            squares_in_view.set(tile_keys(&layer_ids.peek(), &squares_to_load.peek()));
         });
     use_effect(move || {
         let _ = squares_to_load.read();
+        let _ = layer_ids.read();
+        if *animating.read() {
+            return;
+        }
         debounce_update_squares.action(());
     });
     let squares_in_view = use_memo(move || squares_in_view.read().clone());
@@ -67,9 +124,8 @@ pub(crate) fn use_handle_data_loading(
             return;
         }
         let mut _count = 0;
-        let zl = std::collections::HashSet::<(i32, i32, i32)>::from_iter(
-            squares_in_view.peek().iter().cloned(),
-        );
+        let zl =
+            std::collections::HashSet::<TileKey>::from_iter(squares_in_view.peek().iter().cloned());
         let to_delete = map_tile_is_loaded
             .peek()
             .keys()
@@ -81,6 +137,7 @@ pub(crate) fn use_handle_data_loading(
             for d in to_delete {
                 map_tile_is_loaded.write().remove(&d);
                 map_tile_data.write().remove(&d);
+                map_tile_errors.write().remove(&d);
                 _count += 1;
             }
         }
@@ -89,15 +146,30 @@ pub(crate) fn use_handle_data_loading(
         }
     };
 
+    // tiles the server has no imagery for are not asked for again
     let filter_loaded_keys = move |list: &Vec<_>| {
         list.iter()
             .filter(|k| !map_tile_is_loaded.peek().contains_key(k))
+            .filter(|k| {
+                map_tile_errors
+                    .peek()
+                    .get(k)
+                    .map_or(true, TileError::is_retryable)
+            })
             .cloned()
             .collect::<Vec<_>>()
     };
 
     let mut fut = use_future(move || async move {
         let do_stuff = async move {
+            let wait_until = *rate_limited_until.peek();
+            if let Some(wait_until) = wait_until {
+                let now = web_time::Instant::now();
+                if wait_until > now {
+                    info!("rate limited, waiting {:?}", wait_until - now);
+                    async_std::task::sleep(wait_until - now).await;
+                }
+            }
             let _total_count = squares_in_view.len();
             let request_list = filter_loaded_keys(squares_in_view.read().as_ref());
             let _new_conut = request_list.len();
@@ -111,7 +183,7 @@ pub(crate) fn use_handle_data_loading(
             // use futures::stream::FuturesUnordered;
             // pop is slow
             for k in request_list.iter().cloned() {
-                let cache_line = match read_image(k).await {
+                let cache_line = match read_image(k.clone()).await {
                     Ok(cache_line) => cache_line,
                     Err(e) => {
                         error!("failed to read cached img from indexed db: {:#?}", e);
@@ -120,7 +192,7 @@ pub(crate) fn use_handle_data_loading(
                 };
 
                 if let Some(img) = cache_line {
-                    map_tile_data.write().insert(k, img.img_b64);
+                    map_tile_data.write().insert(k.clone(), img.img_b64);
                     map_tile_is_loaded.write().insert(k, true);
                     _read_from_local += 1;
                 }
@@ -134,60 +206,16 @@ pub(crate) fn use_handle_data_loading(
             }
 
             // info!("reading tile list started for {_new_conut} imgs new / {_total_count} total");
-            let request_list_len = request_list.len();
-            match get_tile_list(request_list).await {
-                Ok(x) => {
-                    let mut stream = x.into_inner();
-                    use futures_util::stream::StreamExt;
-                    let mut i = 0;
-                    let mut buf = "".to_string();
-                    while let Some(Ok(chunk)) = stream.next().await {
-                        buf.push_str(&chunk);
-                        let mut lines = vec![];
-                        while let Some(newline_pos) = buf.find("\n") {
-                            lines.push(buf[..newline_pos].to_string());
-                            buf = buf[(newline_pos + 1)..].to_string();
-                        }
-                        for line in lines {
-                            let fields: Vec<_> = line.splitn(5, "|").collect();
-                            // info!("FIELDS: {:?}", fields);
-                            let is_ok = fields[0] == "ok";
-                            let is_ping = fields[0] == "ping";
-                            if is_ping {
-                                info!("pong");
-                                continue;
-                            }
-                            let sq_z = fields[1].parse().unwrap_or(0);
-                            let sq_x = fields[2].parse().unwrap_or(0);
-                            let sq_y = fields[3].parse().unwrap_or(0);
-                            let body = fields[4];
-                            if is_ok {
-                                map_tile_data
-                                    .write()
-                                    .insert((sq_z, sq_x, sq_y), body.to_string());
-                                map_tile_is_loaded.write().insert((sq_z, sq_x, sq_y), true);
-                                // async_std::task::sleep(std::time::Duration::from_millis(1)).await;
-                                if let Err(e) = write_image((sq_z, sq_x, sq_y), &body).await {
-                                    error!(
-                                        "failed to write downloaded image to local storage: {:#?}",
-                                        e
-                                    );
-                                }
-                            } else {
-                                warn!("stream err:  img z={sq_z}/x={sq_x}/y={sq_y}: \n{body}");
-                            }
-                            i += 1;
-                            if i == request_list_len {
-                                // info!("img stream finished all {} tiles.", i);
-                                break;
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    warn!("err fetching img list from  server: {:#?}", e);
-                    return;
-                }
+            for (layer_id, request_list) in group_by_layer(&request_list) {
+                stream_tiles_from_server(
+                    layer_id,
+                    request_list,
+                    map_tile_is_loaded,
+                    map_tile_data,
+                    map_tile_errors,
+                    rate_limited_until,
+                )
+                .await;
             }
         };
 
@@ -216,27 +244,149 @@ pub(crate) fn use_handle_data_loading(
     _r.read();
 }
 
+async fn stream_tiles_from_server(
+    layer_id: String,
+    request_list: Vec<(i32, i32, i32)>,
+    mut map_tile_is_loaded: Signal<HashMap<TileKey, bool>>,
+    mut map_tile_data: Signal<HashMap<TileKey, String>>,
+    mut map_tile_errors: Signal<HashMap<TileKey, TileError>>,
+    mut rate_limited_until: Signal<Option<web_time::Instant>>,
+) {
+    let request_list_len = request_list.len();
+    match get_tile_list(layer_id.clone(), request_list).await {
+        Ok(x) => {
+            let mut stream = x.into_inner();
+            use futures_util::stream::StreamExt;
+            let mut i = 0;
+            let mut decoder = FrameDecoder::default();
+            while let Some(Ok(chunk)) = stream.next().await {
+                decoder.push(&chunk);
+                while let Some(frame) = decoder.next_frame() {
+                    let msg = match frame {
+                        Ok(frame) => frame.msg,
+                        Err(e @ FrameError::Decode(_)) => {
+                            warn!("skipping bad frame from tile stream: {e}");
+                            continue;
+                        }
+                        Err(e) => {
+                            error!("aborting tile stream: {e}");
+                            return;
+                        }
+                    };
+                    let (coord, status, content_type, data) = match msg {
+                        TileMsg::Ping => {
+                            info!("pong");
+                            continue;
+                        }
+                        TileMsg::Tile {
+                            coord,
+                            status,
+                            content_type,
+                            data,
+                        } => (coord, status, content_type, data),
+                        TileMsg::Error { coord, error } => {
+                            let (sq_z, sq_x, sq_y) = coord;
+                            warn!("no tile {layer_id} z={sq_z}/x={sq_x}/y={sq_y}: {error}");
+                            if let TileError::RateLimited { retry_after_secs } = &error {
+                                let secs = retry_after_secs
+                                    .unwrap_or(DEFAULT_RATE_LIMIT_WAIT_SECS)
+                                    .min(MAX_RETRY_AFTER_SECS);
+                                rate_limited_until.set(Some(
+                                    web_time::Instant::now() + std::time::Duration::from_secs(secs),
+                                ));
+                            }
+                            map_tile_errors
+                                .write()
+                                .insert((layer_id.clone(), sq_z, sq_x, sq_y), error);
+                            i += 1;
+                            if i == request_list_len {
+                                return;
+                            }
+                            continue;
+                        }
+                    };
+                    let (sq_z, sq_x, sq_y) = coord;
+                    let key = (layer_id.clone(), sq_z, sq_x, sq_y);
+                    if !(200..300).contains(&status) {
+                        warn!(
+                            "tile {layer_id} z={sq_z}/x={sq_x}/y={sq_y} came with status {status}"
+                        );
+                        map_tile_errors
+                            .write()
+                            .insert(key, TileError::UpstreamStatus(status));
+                        i += 1;
+                        if i == request_list_len {
+                            return;
+                        }
+                        continue;
+                    }
+                    if map_tile_errors.peek().contains_key(&key) {
+                        map_tile_errors.write().remove(&key);
+                    }
+                    let body = TileImage {
+                        content_type,
+                        bytes: data,
+                    }
+                    .to_data_url();
+                    map_tile_data.write().insert(key.clone(), body.clone());
+                    map_tile_is_loaded.write().insert(key.clone(), true);
+                    // async_std::task::sleep(std::time::Duration::from_millis(1)).await;
+                    if let Err(e) = write_image(key, &body).await {
+                        error!(
+                            "failed to write downloaded image to local storage: {:#?}",
+                            e
+                        );
+                    }
+                    i += 1;
+                    if i == request_list_len {
+                        // info!("img stream finished all {} tiles.", i);
+                        return;
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            warn!("err fetching img list from  server: {:#?}", e);
+        }
+    }
+}
+
 // #[server(GetServerTile)]
-use server_fn::codec::StreamingText;
-use server_fn::codec::TextStream;
+use server_fn::codec::ByteStream;
+use server_fn::codec::Streaming;
 
-#[server(output = StreamingText)]
-async fn get_tile_list(list: Vec<(i32, i32, i32)>) -> Result<TextStream, ServerFnError> {
+#[server(output = Streaming)]
+async fn get_tile_list(
+    provider_id: String,
+    list: Vec<(i32, i32, i32)>,
+) -> Result<ByteStream, ServerFnError> {
+    let provider = match crate::tile_provider::tile_provider_registry().get(&provider_id) {
+        Some(provider) => provider,
+        None => {
+            return Err(ServerFnError::new(format!(
+                "unknown tile provider: {provider_id:?}"
+            )))
+        }
+    };
     let (tx, rx) = async_channel::bounded(1);
     const PINGPONG_INTERVAL: f32 = 1.0;
     const SEND_TIMEOUT: f32 = 5.0;
 
     // let (mut tx, rx) = futures::channel::mpsc::unbounded();
     tokio::spawn(async move {
-        let send_msg = move |msg| {
+        let send_msg = move |msg: TileMsg| {
             let mut tx2 = tx.clone();
             async move {
                 if tx2.is_closed() {
                     anyhow::bail!("already closed.");
                 }
+                let frame = match TileFrame::new(msg).encode() {
+                    Ok(frame) => frame,
+                    Err(e) => anyhow::bail!("{e}"),
+                };
                 match tokio::time::timeout(
                     tokio::time::Duration::from_secs_f32(SEND_TIMEOUT),
-                    tx2.send(Ok(msg)),
+                    tx2.send(Ok(frame)),
                 )
                 .await
                 {
@@ -255,18 +405,18 @@ async fn get_tile_list(list: Vec<(i32, i32, i32)>) -> Result<TextStream, ServerF
             }
         };
 
-        let ping_str = "ping|{}|{}|{}|pong\n";
-
-        if let Err(e) = send_msg(ping_str.to_string()).await {
+        if let Err(e) = send_msg(TileMsg::Ping).await {
             warn!("fail to send first ping: {e}");
             return;
         }
 
         use futures::stream::FuturesUnordered;
         let list_len = list.len();
-        info!("server: feteching {} img", list_len);
-        let mut fut_unordered =
-            FuturesUnordered::from_iter(list.into_iter().map(get_server_tile_img));
+        info!("server: feteching {} img from {}", list_len, provider.id);
+        let mut fut_unordered = FuturesUnordered::from_iter(
+            list.into_iter()
+                .map(|coord| get_server_tile_img(provider, coord)),
+        );
         use futures_util::StreamExt;
 
         let mut success_count = 0;
@@ -281,7 +431,7 @@ async fn get_tile_list(list: Vec<(i32, i32, i32)>) -> Result<TextStream, ServerF
                 Err(_timeout) => {
                     // no new traffic - send ping
                     info!("sending ping...");
-                    ping_str.to_string()
+                    TileMsg::Ping
                 }
                 Ok(None) => {
                     // no new futures - stop streaming
@@ -292,14 +442,18 @@ async fn get_tile_list(list: Vec<(i32, i32, i32)>) -> Result<TextStream, ServerF
                     return;
                 }
                 Ok(Some((coord, result))) => match result {
-                    Ok(result) => {
+                    Ok(img) => {
                         success_count += 1;
-                        format!("ok|{}|{}|{}|{}\n", coord.0, coord.1, coord.2, result)
+                        TileMsg::Tile {
+                            coord,
+                            status: 200,
+                            content_type: img.content_type,
+                            data: img.bytes,
+                        }
                     }
-                    Err(err) => {
+                    Err(error) => {
                         err_count += 1;
-                        let err = format!("{:?}", err).as_str()[0..10].to_string();
-                        format!("err|{}|{}|{}|{}\n", coord.0, coord.1, coord.2, err)
+                        TileMsg::Error { coord, error }
                     }
                 },
             };
@@ -316,25 +470,55 @@ async fn get_tile_list(list: Vec<(i32, i32, i32)>) -> Result<TextStream, ServerF
         info!("stream done.");
     });
 
-    Ok(TextStream::new(rx))
+    Ok(ByteStream::new(rx))
 }
 
 #[cfg(feature = "server")]
 async fn get_server_tile_img(
+    provider: &TileProvider,
     coord: (i32, i32, i32),
-) -> ((i32, i32, i32), Result<String, ServerFnError>) {
+) -> ((i32, i32, i32), Result<TileImage, TileError>) {
+    if provider.source.is_local() {
+        return (coord, get_server_tile_img_once(provider, coord).await);
+    }
+
+    let cache = crate::tile_cache::tile_cache();
+    if let Some(img) = cache.get(&provider.id, coord).await {
+        write_mbtiles_sink(provider, coord, &img, false).await;
+        return (coord, Ok(img));
+    }
+
     const RETRIES: u32 = 5; // ~ 64s
     for x in 1..=RETRIES {
-        match get_server_tile_img_once(coord).await {
-            Ok(r) => {
-                return (coord, Ok(r));
+        match get_server_tile_img_once(provider, coord).await {
+            Ok(img) => {
+                cache.put(&provider.id, coord, &img).await;
+                write_mbtiles_sink(provider, coord, &img, true).await;
+                return (coord, Ok(img));
             }
             Err(r) => {
-                info!("ERR {x}/{RETRIES}: {:#?}", r);
-                if x == RETRIES {
-                    return (coord, Err(ServerFnError::new(format!("{r}"))));
+                info!("ERR {x}/{RETRIES} {}/{coord:?}: {r}", provider.id);
+                if x == RETRIES || !r.is_retryable() {
+                    return (coord, Err(r));
                 }
-                let sleep_ms = x as u64 * 250 * 2_u64.pow(x);
+                let sleep_ms = match r {
+                    TileError::RateLimited {
+                        retry_after_secs: Some(secs),
+                    } => {
+                        // long waits belong to the client, which stops asking until then
+                        if secs > MAX_SERVER_RETRY_AFTER_SECS {
+                            let secs = secs.min(MAX_RETRY_AFTER_SECS);
+                            return (
+                                coord,
+                                Err(TileError::RateLimited {
+                                    retry_after_secs: Some(secs),
+                                }),
+                            );
+                        }
+                        secs.saturating_mul(1000)
+                    }
+                    _ => x as u64 * 250 * 2_u64.pow(x),
+                };
                 // info!("failed to get tile img; chance {x}/{RETRIES}; sleep {sleep_ms}ms");
                 tokio::time::sleep(tokio::time::Duration::from_millis(sleep_ms)).await;
                 continue;
@@ -344,37 +528,116 @@ async fn get_server_tile_img(
     unreachable!();
 }
 
+/// Copies a tile of an http provider into its `mbtiles_sink`, if it has one. Disk cache
+/// hits pass `replace: false`: they only fill in tiles the sink is missing, so the export
+/// is complete without rewriting a tile every time it is viewed.
+#[cfg(feature = "server")]
+async fn write_mbtiles_sink(
+    provider: &TileProvider,
+    coord: (i32, i32, i32),
+    img: &TileImage,
+    replace: bool,
+) {
+    let TileSource::Http(HttpTileSource {
+        mbtiles_sink: Some(sink),
+        ..
+    }) = &provider.source
+    else {
+        return;
+    };
+    if !replace {
+        match crate::mbtiles::has_tile(sink, coord).await {
+            Ok(false) => {}
+            Ok(true) => return,
+            Err(e) => {
+                warn!("failed to look up tile {coord:?} in {sink:?}: {e:#}");
+                return;
+            }
+        }
+    }
+    if let Err(e) = crate::mbtiles::write_tile(sink, coord, img.clone()).await {
+        warn!("failed to write tile {coord:?} into {sink:?}: {e:#}");
+    }
+}
+
 #[cfg(feature = "server")]
-async fn get_server_tile_img_once(coord: (i32, i32, i32)) -> anyhow::Result<String> {
-    let (sq_z, sq_x, sq_y) = coord;
-    // let url = format!("http://localhost:8000/api/tile/google_hybrid/{sq_z}/{sq_x}/{sq_y}/jpg");
-    // let url = format!("https://tile.openstreetmap.org/{sq_z}/{sq_x}/{sq_y}.png");
-    let url = format!("https://mt1.google.com/vt/lyrs=y&x={sq_x}&y={sq_y}&z={sq_z}");
+async fn get_server_tile_img_once(
+    provider: &TileProvider,
+    coord: (i32, i32, i32),
+) -> Result<TileImage, TileError> {
+    if coord.0 > provider.max_zoom {
+        return Err(TileError::NotFound);
+    }
+    let local_tile = match &provider.source {
+        TileSource::Http(source) => return fetch_http_tile(source, coord).await,
+        TileSource::Mbtiles(source) => crate::mbtiles::read_tile(&source.path, coord).await,
+        TileSource::Pmtiles(source) => crate::pmtiles::read_tile(&source.path, coord).await,
+    };
+    match local_tile {
+        Ok(Some(img)) => Ok(img),
+        Ok(None) => Err(TileError::NotFound),
+        Err(e) => Err(TileError::Other(format!("{e:#}"))),
+    }
+}
+
+#[cfg(feature = "server")]
+async fn fetch_http_tile(
+    source: &HttpTileSource,
+    coord: (i32, i32, i32),
+) -> Result<TileImage, TileError> {
+    const REQUEST_TIMEOUT: f32 = 20.0;
+    let url = source.tile_url(coord);
+    let request_error = |e: reqwest::Error| {
+        if e.is_timeout() {
+            TileError::Timeout
+        } else {
+            TileError::Other(format!("{url}: {e}"))
+        }
+    };
 
     let client = reqwest::Client::builder()
-        .user_agent("Mozilla/5.0 (X11; Ubuntu; Linux i686; rv:133.0) Gecko/20100101 Firefox/133.0")
-        .build()?;
+        .user_agent(source.user_agent())
+        .timeout(std::time::Duration::from_secs_f32(REQUEST_TIMEOUT))
+        .build()
+        .map_err(request_error)?;
 
-    let response = anyhow::Context::context(client.get(&url).send().await, "reqwest send error:")?;
-    let status_code = response.status().clone();
+    let mut request = client.get(&url);
+    for (header, value) in source.headers.iter() {
+        request = request.header(header, value);
+    }
+    let response = request.send().await.map_err(request_error)?;
+    let status_code = response.status();
+    match status_code.as_u16() {
+        404 | 204 => return Err(TileError::NotFound),
+        429 => {
+            let retry_after_secs = response
+                .headers()
+                .get("Retry-After")
+                .and_then(|x| x.to_str().ok())
+                .and_then(|x| x.trim().parse().ok());
+            return Err(TileError::RateLimited { retry_after_secs });
+        }
+        status if !status_code.is_success() => return Err(TileError::UpstreamStatus(status)),
+        _ => {}
+    }
     let content_type = response
         .headers()
         .get("Content-Type")
         .map(|x| x.to_str().unwrap_or("image/png"))
         .unwrap_or("image/png")
         .to_string();
-    if !status_code.is_success() || content_type.len() < 4 {
-        anyhow::bail!(
-            "bad response from tile server: {:?}, url:{:?} err: {:?}",
-            status_code,
-            url,
-            response.text().await
-        );
+    if !content_type.starts_with("image/") {
+        return Err(TileError::Decode(format!(
+            "content type {content_type:?} from {url}"
+        )));
     }
 
-    let resp_bytes = anyhow::Context::context(response.bytes().await, "reqwest read bytes: ")?;
-    let resp_base64 = base64::prelude::BASE64_STANDARD.encode(resp_bytes);
-
-    let img_src = format!("data:{content_type};base64,{resp_base64}");
-    Ok(img_src)
+    let resp_bytes = response.bytes().await.map_err(request_error)?;
+    if resp_bytes.is_empty() {
+        return Err(TileError::Decode(format!("empty body from {url}")));
+    }
+    Ok(TileImage {
+        content_type,
+        bytes: resp_bytes.to_vec(),
+    })
 }
@@ -3,52 +3,64 @@ use crate::_const::{MAX_Z, MIN_Z, REF_Z};
 fn get_tile_positions_one_level(
     pos: (f64, f64),
     zoom: f64,
+    bearing: f64,
     dimensions: (f64, f64),
     max_pic_pixels: f64,
 ) -> Vec<(i32, i32, i32)> {
+    // the tile size on screen comes from the real screen, the area to cover
+    // from the axis-aligned box around the rotated screen
     let vmin_px = f64::min(dimensions.0, dimensions.1);
+    let cover = rotated_bounding_box(dimensions, bearing);
     let min_dim_tiles = vmin_px / max_pic_pixels;
     let ideal_tile_level = (f64::trunc(f64::log2(min_dim_tiles) + zoom) as i32).clamp(MIN_Z, MAX_Z);
 
     let x0 = (pos.0 / f64::exp2(REF_Z - ideal_tile_level as f64)).floor() as i32;
     let y0 = (pos.1 / f64::exp2(REF_Z - ideal_tile_level as f64)).floor() as i32;
     let remainder0 = f64::exp2(ideal_tile_level as f64) as i32;
-    let x0 = x0.rem_euclid(remainder0);
-    let y0 = y0.rem_euclid(remainder0);
 
     let mut ze_squarez = vec![];
-    let tile_diff_exp = f64::exp(f64::fract(f64::log2(min_dim_tiles) + zoom));
-    let tile_count_x =
-        (dimensions.0 / vmin_px / tile_diff_exp * min_dim_tiles + 1.1).ceil() as i32 + 1;
-    let tile_count_y =
-        (dimensions.1 / vmin_px / tile_diff_exp * min_dim_tiles + 1.1).ceil() as i32 + 1;
+    // size of a screen tile (half of vmin, see `MapsTile`) in tiles of `ideal_tile_level`
+    let tile_diff_exp = f64::exp2(ideal_tile_level as f64 - zoom);
+    let tile_count_x = (cover.0 / vmin_px * tile_diff_exp + 1.1).ceil() as i32 + 1;
+    let tile_count_y = (cover.1 / vmin_px * tile_diff_exp + 1.1).ceil() as i32 + 1;
+    // the world repeats east-west, so x is left unwrapped (see `wrap_tile`);
+    // there is nothing past the poles
+    let y_range = y0.saturating_sub(tile_count_y).max(0)
+        ..=y0.saturating_add(tile_count_y).min(remainder0 - 1);
     for i in (x0 - tile_count_x)..=(x0 + tile_count_x) {
-        for j in (y0 - tile_count_y)..=(y0 + tile_count_y) {
-            ze_squarez.push((
-                ideal_tile_level,
-                i.rem_euclid(remainder0),
-                j.rem_euclid(remainder0),
-            ));
+        for j in y_range.clone() {
+            ze_squarez.push((ideal_tile_level, i, j));
         }
     }
 
     ze_squarez
 }
 
+/// a tile image is never drawn more than this many times its pixel size
+const IMG_MAX_STRETCH: f64 = 1.5;
+
 /// Computes (squares to load in memory, squares to put on screen)
+///
+/// x is not wrapped, so that tiles across the antimeridian sit next to the view:
+/// `wrap_tile` gives the tile to load and the world copy to draw it in.
+///
+/// `tile_px` is the pixel size of the tile images, and `pixel_ratio` the image
+/// pixels wanted per CSS pixel: the device pixel ratio, times the quality setting.
 pub(crate) fn get_tile_positions(
     pos: (f64, f64),
     zoom: f64,
+    bearing: f64,
     dimensions: (f64, f64),
+    tile_px: f64,
+    pixel_ratio: f64,
 ) -> Vec<(i32, i32, i32)> {
     if zoom < MIN_Z as f64 - 0.0001 {
         return vec![];
     }
-    const IMG_MAX_PX: f64 = 256.0 + 128.0;
-    let mut current_px = IMG_MAX_PX;
+    let mut current_px = tile_px * IMG_MAX_STRETCH / pixel_ratio.max(0.1);
     let mut all_sq = vec![];
     for _ in 0..5 {
-        let mut new_sq = get_tile_positions_one_level(pos, zoom, dimensions, current_px);
+        let mut new_sq = get_tile_positions_one_level(pos, zoom, bearing, dimensions, current_px);
         if new_sq.is_empty() {
             break;
         }
@@ -63,3 +75,576 @@ pub(crate) fn get_tile_positions(
     all_sq.dedup();
     all_sq
 }
+
+/// Splits a tile from `get_tile_positions` into the real tile, x in `0..2^z`, and the
+/// world copy it is drawn in: 0 for the world around `(0, 0)`, -1 west of it, 1 east.
+pub(crate) fn wrap_tile(tile: (i32, i32, i32)) -> ((i32, i32, i32), i32) {
+    let (sq_z, sq_x, sq_y) = tile;
+    let count = 1 << sq_z;
+    ((sq_z, sq_x.rem_euclid(count), sq_y), sq_x.div_euclid(count))
+}
+
+/// The tile `levels` zoom levels up that contains `(z, x, y)`.
+pub(crate) fn tile_ancestor(tile: (i32, i32, i32), levels: i32) -> (i32, i32, i32) {
+    let (sq_z, sq_x, sq_y) = tile;
+    (sq_z - levels, sq_x >> levels, sq_y >> levels)
+}
+
+/// The four tiles one zoom level down that cover `(z, x, y)`.
+pub(crate) fn tile_children(tile: (i32, i32, i32)) -> [(i32, i32, i32); 4] {
+    let (sq_z, sq_x, sq_y) = tile;
+    let (x, y) = (sq_x * 2, sq_y * 2);
+    [
+        (sq_z + 1, x, y),
+        (sq_z + 1, x + 1, y),
+        (sq_z + 1, x, y + 1),
+        (sq_z + 1, x + 1, y + 1),
+    ]
+}
+
+/// WGS84 equatorial radius, the sphere Web Mercator projects from.
+pub const EARTH_RADIUS_M: f64 = 6_378_137.0;
+/// Web Mercator cuts the world off here so that it is square.
+pub const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+/// Pixel size of one tile image, the unit `meters_per_pixel` counts in.
+pub const TILE_PX: f64 = 256.0;
+
+/// Latitude/longitude box, in degrees: a tile, or the extent of an overlay image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatLonBounds {
+    pub north: f64,
+    pub south: f64,
+    pub west: f64,
+    pub east: f64,
+}
+
+/// Size of the whole world in `MapState.pos` units: tiles at zoom `REF_Z`.
+pub fn world_size() -> f64 {
+    f64::exp2(REF_Z)
+}
+
+/// Keeps `MapState.pos` inside the world: x wraps around the antimeridian, y stops
+/// at the poles.
+pub fn clamp_world_pos(pos: (f64, f64)) -> (f64, f64) {
+    (
+        pos.0.rem_euclid(world_size()),
+        pos.1.clamp(0.0, world_size()),
+    )
+}
+
+/// The copy of `world` (shifted by whole world widths in x) closest to `pos`, so that
+/// things near the antimeridian are drawn on the side of it the camera looks at.
+pub fn nearest_world_copy(pos: (f64, f64), world: (f64, f64)) -> (f64, f64) {
+    let size = world_size();
+    (world.0 + ((pos.0 - world.0) / size).round() * size, world.1)
+}
+
+/// Projects WGS84 `(lat, lon)` in degrees to world coordinates, the unit of `MapState.pos`:
+/// `(0, 0)` is the north-west corner, x grows east and y grows south.
+pub fn lat_lon_to_world(lat_lon: (f64, f64)) -> (f64, f64) {
+    let (lat, lon) = lat_lon;
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (lon + 180.0) / 360.0;
+    let y = (1.0 - f64::ln(f64::tan(lat) + 1.0 / f64::cos(lat)) / std::f64::consts::PI) / 2.0;
+    (x * world_size(), y * world_size())
+}
+
+/// Inverse of [`lat_lon_to_world`]. Longitude is not wrapped into `-180..180`.
+pub fn world_to_lat_lon(pos: (f64, f64)) -> (f64, f64) {
+    let x = pos.0 / world_size();
+    let y = pos.1 / world_size();
+    let lon = x * 360.0 - 180.0;
+    let lat = f64::atan(f64::sinh(std::f64::consts::PI * (1.0 - 2.0 * y))).to_degrees();
+    (lat, lon)
+}
+
+/// The tile at zoom `sq_z` that contains the world point `pos`.
+pub fn world_to_tile(pos: (f64, f64), sq_z: i32) -> (i32, i32, i32) {
+    let tile_size = f64::exp2(REF_Z - sq_z as f64);
+    let max = (1i64 << sq_z) - 1;
+    let sq_x = ((pos.0 / tile_size).floor() as i64).clamp(0, max) as i32;
+    let sq_y = ((pos.1 / tile_size).floor() as i64).clamp(0, max) as i32;
+    (sq_z, sq_x, sq_y)
+}
+
+/// The tile at zoom `sq_z` that contains `(lat, lon)`.
+pub fn lat_lon_to_tile(lat_lon: (f64, f64), sq_z: i32) -> (i32, i32, i32) {
+    world_to_tile(lat_lon_to_world(lat_lon), sq_z)
+}
+
+/// World coordinates of the north-west corner of a tile.
+pub fn tile_to_world(tile: (i32, i32, i32)) -> (f64, f64) {
+    let (sq_z, sq_x, sq_y) = tile;
+    let tile_size = f64::exp2(REF_Z - sq_z as f64);
+    (sq_x as f64 * tile_size, sq_y as f64 * tile_size)
+}
+
+pub fn tile_bounds(tile: (i32, i32, i32)) -> LatLonBounds {
+    let (sq_z, sq_x, sq_y) = tile;
+    let (north, west) = world_to_lat_lon(tile_to_world(tile));
+    let (south, east) = world_to_lat_lon(tile_to_world((sq_z, sq_x + 1, sq_y + 1)));
+    LatLonBounds {
+        north,
+        south,
+        west,
+        east,
+    }
+}
+
+/// Ground meters covered by one pixel of a [`TILE_PX`] tile shown at `zoom`, at latitude `lat`.
+pub fn meters_per_pixel(lat: f64, zoom: f64) -> f64 {
+    let equator = 2.0 * std::f64::consts::PI * EARTH_RADIUS_M;
+    equator * f64::cos(lat.to_radians()) / (TILE_PX * f64::exp2(zoom))
+}
+
+/// Mean earth radius (IUGG), for distances on a sphere.
+pub const MEAN_EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// Great-circle distance in meters between two `(lat, lon)` points (haversine).
+pub fn distance_m(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (a.0.to_radians(), b.0.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (b.1 - a.1).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * MEAN_EARTH_RADIUS_M * h.sqrt().min(1.0).asin()
+}
+
+/// Turns the vector `v` clockwise on screen (y grows down) by `degrees`.
+pub fn rotate(v: (f64, f64), degrees: f64) -> (f64, f64) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    (v.0 * cos - v.1 * sin, v.0 * sin + v.1 * cos)
+}
+
+/// Size of the axis-aligned box around a `dimensions` rectangle turned by `bearing`.
+pub fn rotated_bounding_box(dimensions: (f64, f64), bearing: f64) -> (f64, f64) {
+    let (sin, cos) = bearing.to_radians().sin_cos();
+    let (sin, cos) = (sin.abs(), cos.abs());
+    (
+        dimensions.0 * cos + dimensions.1 * sin,
+        dimensions.0 * sin + dimensions.1 * cos,
+    )
+}
+
+/// World point under the screen pixel `screen_px` (page coordinates), for a map
+/// centered on `pos` at `zoom` and turned so that `bearing` (degrees clockwise
+/// from north) points up. Half of the smaller screen side is one tile at `zoom`.
+pub fn screen_to_world(
+    pos: (f64, f64),
+    zoom: f64,
+    bearing: f64,
+    dimensions: (f64, f64),
+    screen_px: (f64, f64),
+) -> (f64, f64) {
+    let quad_edge = f64::min(dimensions.0, dimensions.1) / 2.0;
+    let exp = f64::exp2(REF_Z - zoom);
+    let offset = rotate(
+        (
+            screen_px.0 - dimensions.0 / 2.0,
+            screen_px.1 - dimensions.1 / 2.0,
+        ),
+        bearing,
+    );
+    (
+        pos.0 + offset.0 / quad_edge * exp,
+        pos.1 + offset.1 / quad_edge * exp,
+    )
+}
+
+/// Inverse of [`screen_to_world`].
+pub fn world_to_screen(
+    pos: (f64, f64),
+    zoom: f64,
+    bearing: f64,
+    dimensions: (f64, f64),
+    world: (f64, f64),
+) -> (f64, f64) {
+    let quad_edge = f64::min(dimensions.0, dimensions.1) / 2.0;
+    let exp = f64::exp2(REF_Z - zoom);
+    let offset = rotate(
+        (
+            (world.0 - pos.0) / exp * quad_edge,
+            (world.1 - pos.1) / exp * quad_edge,
+        ),
+        -bearing,
+    );
+    (offset.0 + dimensions.0 / 2.0, offset.1 + dimensions.1 / 2.0)
+}
+
+/// Axis-aligned world box `(min, max)` around everything the screen shows, grown by
+/// `margin_px` screen pixels on every side.
+pub fn visible_world_bounds(
+    pos: (f64, f64),
+    zoom: f64,
+    bearing: f64,
+    dimensions: (f64, f64),
+    margin_px: f64,
+) -> ((f64, f64), (f64, f64)) {
+    let quad_edge = f64::min(dimensions.0, dimensions.1) / 2.0;
+    let exp = f64::exp2(REF_Z - zoom);
+    let cover = rotated_bounding_box(dimensions, bearing);
+    let half = (
+        (cover.0 / 2.0 + margin_px) / quad_edge * exp,
+        (cover.1 / 2.0 + margin_px) / quad_edge * exp,
+    );
+    (
+        (pos.0 - half.0, pos.1 - half.1),
+        (pos.0 + half.0, pos.1 + half.1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64, eps: f64) {
+        assert!((a - b).abs() < eps, "{a} != {b} (eps {eps})");
+    }
+
+    #[test]
+    fn null_island_is_world_center() {
+        let (x, y) = lat_lon_to_world((0.0, 0.0));
+        assert_close(x, world_size() / 2.0, 1e-9);
+        assert_close(y, world_size() / 2.0, 1e-9);
+    }
+
+    #[test]
+    fn corners_of_the_world() {
+        let (x, y) = lat_lon_to_world((MAX_LATITUDE, -180.0));
+        assert_close(x, 0.0, 1e-9);
+        assert_close(y, 0.0, 1e-6);
+        let (x, y) = lat_lon_to_world((-MAX_LATITUDE, 180.0));
+        assert_close(x, world_size(), 1e-9);
+        assert_close(y, world_size(), 1e-6);
+    }
+
+    #[test]
+    fn latitude_is_clamped() {
+        assert_eq!(
+            lat_lon_to_world((90.0, 0.0)),
+            lat_lon_to_world((MAX_LATITUDE, 0.0))
+        );
+        assert_eq!(
+            lat_lon_to_world((-90.0, 0.0)),
+            lat_lon_to_world((-MAX_LATITUDE, 0.0))
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for lat_lon in [
+            (44.4268, 26.1025),
+            (51.5074, -0.1278),
+            (-33.8688, 151.2093),
+            (0.0, 0.0),
+        ] {
+            let (lat, lon) = world_to_lat_lon(lat_lon_to_world(lat_lon));
+            assert_close(lat, lat_lon.0, 1e-9);
+            assert_close(lon, lat_lon.1, 1e-9);
+        }
+    }
+
+    #[test]
+    fn init_state_is_bucharest() {
+        let (lat, lon) = world_to_lat_lon(crate::url_state::INIT_STATE.pos);
+        assert_close(lat, 44.43, 0.05);
+        assert_close(lon, 26.10, 0.05);
+    }
+
+    #[test]
+    fn known_tiles() {
+        // London, Big Ben - from the lon/lat to tile formula on the OSM wiki
+        assert_eq!(lat_lon_to_tile((51.5007, -0.1246), 10), (10, 511, 340));
+        assert_eq!(lat_lon_to_tile((51.5007, -0.1246), 16), (16, 32745, 21794));
+        assert_eq!(lat_lon_to_tile((0.0, 0.0), 0), (0, 0, 0));
+        assert_eq!(lat_lon_to_tile((-89.0, 179.9999), 3), (3, 7, 7));
+    }
+
+    #[test]
+    fn tile_bounds_of_first_quadrant() {
+        let b = tile_bounds((1, 0, 0));
+        assert_close(b.north, MAX_LATITUDE, 1e-9);
+        assert_close(b.south, 0.0, 1e-9);
+        assert_close(b.west, -180.0, 1e-9);
+        assert_close(b.east, 0.0, 1e-9);
+    }
+
+    #[test]
+    fn tile_bounds_contain_their_points() {
+        let lat_lon = (44.4268, 26.1025);
+        let b = tile_bounds(lat_lon_to_tile(lat_lon, 15));
+        assert!(b.south <= lat_lon.0 && lat_lon.0 < b.north);
+        assert!(b.west <= lat_lon.1 && lat_lon.1 < b.east);
+    }
+
+    #[test]
+    fn meters_per_pixel_reference_values() {
+        // the usual Web Mercator ground resolution table
+        assert_close(meters_per_pixel(0.0, 0.0), 156_543.033_928, 1e-5);
+        assert_close(meters_per_pixel(0.0, 18.0), 0.597_164, 1e-6);
+        assert_close(meters_per_pixel(60.0, 0.0), 78_271.516_964, 1e-5);
+    }
+
+    /// xorshift64*, so the property tests need no extra dependency and are reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        /// uniform in `lo..hi`
+        fn range(&mut self, lo: f64, hi: f64) -> f64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            let bits = self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+            lo + (hi - lo) * (bits as f64 / (1u64 << 53) as f64)
+        }
+    }
+
+    /// Asserts that every screen pixel showing the world is covered by one of `tiles`.
+    /// The tiles form a rectangle in tile space (modulo the x wrap) and the screen maps
+    /// to a convex quad, so checking a grid that includes the four corners is enough.
+    fn assert_screen_covered(
+        tiles: &[(i32, i32, i32)],
+        pos: (f64, f64),
+        zoom: f64,
+        bearing: f64,
+        dimensions: (f64, f64),
+    ) {
+        const STEPS: usize = 8;
+        let sq_z = tiles[0].0;
+        let tile_size = f64::exp2(REF_Z - sq_z as f64);
+        for i in 0..=STEPS {
+            for j in 0..=STEPS {
+                let px = (
+                    dimensions.0 * i as f64 / STEPS as f64,
+                    dimensions.1 * j as f64 / STEPS as f64,
+                );
+                let world = screen_to_world(pos, zoom, bearing, dimensions, px);
+                // past the poles there is nothing to draw
+                if world.1 < 0.0 || world.1 >= world_size() {
+                    continue;
+                }
+                // tiles are placed by their unwrapped x, so this also checks the world copy
+                let sq_x = (world.0 / tile_size).floor() as i32;
+                let sq_y = (world.1 / tile_size).floor() as i32;
+                assert!(
+                    tiles.contains(&(sq_z, sq_x, sq_y)),
+                    "pixel {px:?} needs tile {:?}; pos {pos:?} zoom {zoom} bearing {bearing} \
+                     dimensions {dimensions:?}",
+                    (sq_z, sq_x, sq_y),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn random_viewports_are_covered() {
+        let mut rng = Rng(0x1234_5678_9abc_def1);
+        for _ in 0..2000 {
+            let dimensions = (rng.range(100.0, 4000.0), rng.range(100.0, 4000.0));
+            let zoom = rng.range(MIN_Z as f64, MAX_Z as f64 + 2.0);
+            let pos = (rng.range(0.0, world_size()), rng.range(0.0, world_size()));
+            let bearing = if rng.range(0.0, 1.0) < 0.5 {
+                0.0
+            } else {
+                rng.range(0.0, 360.0)
+            };
+            let max_pic_pixels = rng.range(128.0, 1536.0);
+            let tiles =
+                get_tile_positions_one_level(pos, zoom, bearing, dimensions, max_pic_pixels);
+            assert!(!tiles.is_empty());
+            assert!(tiles.iter().all(|t| t.0 == tiles[0].0));
+            assert_screen_covered(&tiles, pos, zoom, bearing, dimensions);
+        }
+    }
+
+    #[test]
+    fn chosen_level_keeps_tiles_sharp() {
+        let mut rng = Rng(0x0bad_cafe_f00d_beef);
+        for _ in 0..2000 {
+            let dimensions = (rng.range(100.0, 4000.0), rng.range(100.0, 4000.0));
+            let zoom = rng.range(MIN_Z as f64, MAX_Z as f64);
+            let max_pic_pixels = rng.range(128.0, 1536.0);
+            let tiles = get_tile_positions_one_level(
+                (world_size() / 2.0, world_size() / 2.0),
+                zoom,
+                0.0,
+                dimensions,
+                max_pic_pixels,
+            );
+            let sq_z = tiles[0].0;
+            // one tile at `zoom` is half of vmin wide
+            let tile_px =
+                f64::min(dimensions.0, dimensions.1) / 2.0 * f64::exp2(zoom - sq_z as f64);
+            if sq_z < MAX_Z {
+                assert!(
+                    tile_px <= max_pic_pixels * 1.0001,
+                    "{tile_px} > {max_pic_pixels}"
+                );
+            }
+            if sq_z > MIN_Z {
+                assert!(
+                    tile_px >= max_pic_pixels / 2.0 * 0.9999,
+                    "{tile_px} < {max_pic_pixels} / 2"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn all_levels_are_covered() {
+        let mut rng = Rng(0x5eed_5eed_5eed_5eed);
+        for _ in 0..200 {
+            let dimensions = (rng.range(100.0, 2500.0), rng.range(100.0, 2500.0));
+            let zoom = rng.range(MIN_Z as f64, MAX_Z as f64);
+            let pos = (rng.range(0.0, world_size()), rng.range(0.0, world_size()));
+            let bearing = rng.range(0.0, 360.0);
+            let tiles =
+                get_tile_positions(pos, zoom, bearing, dimensions, TILE_PX, rng.range(0.5, 3.0));
+            let mut levels = tiles.iter().map(|t| t.0).collect::<Vec<_>>();
+            levels.dedup();
+            for sq_z in levels {
+                let level = tiles
+                    .iter()
+                    .copied()
+                    .filter(|t| t.0 == sq_z)
+                    .collect::<Vec<_>>();
+                assert_screen_covered(&level, pos, zoom, bearing, dimensions);
+            }
+        }
+    }
+
+    #[test]
+    fn antimeridian_wraps_x() {
+        let dimensions = (1600.0, 900.0);
+        for (pos_x, other_copy) in [(0.25, -1), (world_size() - 0.25, 1)] {
+            let pos = (pos_x, world_size() / 2.0);
+            let tiles = get_tile_positions_one_level(pos, 6.0, 0.0, dimensions, 384.0);
+            let sq_z = tiles[0].0;
+            let count = 1 << sq_z;
+            let wrapped = tiles.iter().map(|t| wrap_tile(*t)).collect::<Vec<_>>();
+            assert!(wrapped.iter().all(|(t, _)| (0..count).contains(&t.1)));
+            assert!(wrapped
+                .iter()
+                .all(|(_, copy)| [0, other_copy].contains(copy)));
+            // the far side of the antimeridian comes from the next world copy
+            assert!(wrapped.contains(&((sq_z, 0, count / 2), 0.max(other_copy))));
+            assert!(wrapped.contains(&((sq_z, count - 1, count / 2), 0.min(other_copy))));
+            assert_screen_covered(&tiles, pos, 6.0, 0.0, dimensions);
+        }
+    }
+
+    #[test]
+    fn ancestors_contain_their_descendants() {
+        assert_eq!(tile_ancestor((5, 13, 22), 0), (5, 13, 22));
+        assert_eq!(tile_ancestor((5, 13, 22), 2), (3, 3, 5));
+        assert_eq!(tile_ancestor((5, 13, 22), 5), (0, 0, 0));
+        for child in tile_children((3, 3, 5)) {
+            assert_eq!(tile_ancestor(child, 1), (3, 3, 5));
+        }
+    }
+
+    #[test]
+    fn nearest_world_copy_follows_the_camera() {
+        let size = world_size();
+        let y = size / 2.0;
+        // just east of the antimeridian, seen from just west of it
+        assert_eq!(
+            nearest_world_copy((size - 0.25, y), (0.5, y)),
+            (size + 0.5, y)
+        );
+        assert_eq!(nearest_world_copy((0.25, y), (size - 0.5, y)), (-0.5, y));
+        assert_eq!(nearest_world_copy((size / 2.0, y), (10.0, y)), (10.0, y));
+        // an unwrapped camera x, several worlds away
+        assert_eq!(
+            nearest_world_copy((3.0 * size + 1.0, y), (2.0, 7.0)),
+            (3.0 * size + 2.0, 7.0)
+        );
+        // projected where the tiles of that copy are
+        let pos = (size - 0.25, y);
+        let screen = world_to_screen(
+            pos,
+            REF_Z,
+            0.0,
+            (800.0, 600.0),
+            nearest_world_copy(pos, (0.5, y)),
+        );
+        assert_close(screen.0, 400.0 + 0.75 * 300.0, 1e-6);
+    }
+
+    #[test]
+    fn visible_bounds_hold_the_screen_corners() {
+        let mut rng = Rng(0xfeed_beef_0123_4567);
+        for _ in 0..500 {
+            let dimensions = (rng.range(100.0, 4000.0), rng.range(100.0, 4000.0));
+            let zoom = rng.range(MIN_Z as f64, MAX_Z as f64);
+            let pos = (rng.range(0.0, world_size()), rng.range(0.0, world_size()));
+            let bearing = rng.range(0.0, 360.0);
+            let (min, max) = visible_world_bounds(pos, zoom, bearing, dimensions, 0.0);
+            for corner in [
+                (0.0, 0.0),
+                (dimensions.0, 0.0),
+                (0.0, dimensions.1),
+                (dimensions.0, dimensions.1),
+            ] {
+                let world = screen_to_world(pos, zoom, bearing, dimensions, corner);
+                let eps = (max.0 - min.0) * 1e-9;
+                assert!(world.0 >= min.0 - eps && world.0 <= max.0 + eps);
+                assert!(world.1 >= min.1 - eps && world.1 <= max.1 + eps);
+            }
+        }
+        // north-up, the box is the screen itself
+        let (min, max) = visible_world_bounds((100.0, 100.0), REF_Z, 0.0, (400.0, 200.0), 0.0);
+        assert_close(min.0, 98.0, 1e-9);
+        assert_close(max.1, 101.0, 1e-9);
+        let (min, _) = visible_world_bounds((100.0, 100.0), REF_Z, 0.0, (400.0, 200.0), 50.0);
+        assert_close(min.0, 97.5, 1e-9);
+    }
+
+    #[test]
+    fn wrap_tile_splits_world_copies() {
+        assert_eq!(wrap_tile((3, 5, 2)), ((3, 5, 2), 0));
+        assert_eq!(wrap_tile((3, -1, 2)), ((3, 7, 2), -1));
+        assert_eq!(wrap_tile((3, 8, 2)), ((3, 0, 2), 1));
+        assert_eq!(wrap_tile((0, -3, 0)), ((0, 0, 0), -3));
+    }
+
+    #[test]
+    fn world_pos_wraps_x_and_clamps_y() {
+        let w = world_size();
+        assert_eq!(clamp_world_pos((10.0, 20.0)), (10.0, 20.0));
+        assert_eq!(clamp_world_pos((-10.0, -20.0)), (w - 10.0, 0.0));
+        assert_eq!(clamp_world_pos((w + 10.0, w + 20.0)), (10.0, w));
+    }
+
+    #[test]
+    fn poles_clamp_y() {
+        let dimensions = (1600.0, 900.0);
+        for (pos_y, zoom) in [
+            (0.5, 4.0),
+            (world_size() - 0.5, 4.0),
+            (1.0, 9.5),
+            (-1000.0, 9.5),
+        ] {
+            let pos = (world_size() / 2.0, pos_y);
+            let tiles = get_tile_positions_one_level(pos, zoom, 0.0, dimensions, 384.0);
+            let count = 1 << tiles[0].0;
+            // a view of one pole never shows tiles of the other one
+            let near_north = pos_y < world_size() / 2.0;
+            for (_, _, sq_y) in tiles.iter().copied() {
+                assert!((0..count).contains(&sq_y), "y {sq_y} outside 0..{count}");
+                if near_north {
+                    assert!(sq_y < count / 2, "north pole view shows south tile {sq_y}");
+                } else {
+                    assert!(sq_y >= count / 2, "south pole view shows north tile {sq_y}");
+                }
+            }
+            assert_screen_covered(&tiles, pos, zoom, 0.0, dimensions);
+        }
+    }
+
+    #[test]
+    fn far_past_the_pole_shows_nothing() {
+        let pos = (world_size() / 2.0, -world_size());
+        assert!(get_tile_positions_one_level(pos, 10.0, 0.0, (1000.0, 1000.0), 384.0).is_empty());
+        assert!(get_tile_positions(pos, 10.0, 0.0, (1000.0, 1000.0), TILE_PX, 1.0).is_empty());
+    }
+}
@@ -0,0 +1,512 @@
+//! GPX 1.1 import and export: waypoints, routes and tracks, plus track statistics.
+//!
+//! Pure Rust on top of `roxmltree`, so it runs in the wasm client and in native tests.
+
+use crate::geometry::distance_m;
+use crate::measure::Measurement;
+use crate::overlay::{Feature, FeatureStyle, Geometry, VectorLayer};
+
+const GPX_NS: &str = "http://www.topografix.com/GPX/1/1";
+/// elevation changes smaller than this are GPS noise and do not count towards gain/loss
+const ELEVATION_NOISE_M: f64 = 2.0;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GpxPoint {
+    pub lat: f64,
+    pub lon: f64,
+    pub ele: Option<f64>,
+    /// kept as written (RFC 3339), so that export gives back the same text
+    pub time: Option<String>,
+    pub name: Option<String>,
+}
+
+/// A route (one segment) or a track (one or more segments).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GpxPath {
+    pub name: Option<String>,
+    pub segments: Vec<Vec<GpxPoint>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Gpx {
+    pub waypoints: Vec<GpxPoint>,
+    pub routes: Vec<GpxPath>,
+    pub tracks: Vec<GpxPath>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TrackStats {
+    pub distance_m: f64,
+    pub elevation_gain_m: f64,
+    pub elevation_loss_m: f64,
+    /// from the first to the last timestamp, if the points have any
+    pub duration_s: Option<f64>,
+}
+
+impl GpxPath {
+    pub fn stats(&self) -> TrackStats {
+        let mut stats = TrackStats::default();
+        let mut first_time = None;
+        let mut last_time = None;
+        for segment in self.segments.iter() {
+            let mut last_ele: Option<f64> = None;
+            for (i, p) in segment.iter().enumerate() {
+                if i > 0 {
+                    let prev = &segment[i - 1];
+                    stats.distance_m += distance_m((prev.lat, prev.lon), (p.lat, p.lon));
+                }
+                if let Some(ele) = p.ele {
+                    match last_ele {
+                        Some(last) if ele - last >= ELEVATION_NOISE_M => {
+                            stats.elevation_gain_m += ele - last;
+                            last_ele = Some(ele);
+                        }
+                        Some(last) if last - ele >= ELEVATION_NOISE_M => {
+                            stats.elevation_loss_m += last - ele;
+                            last_ele = Some(ele);
+                        }
+                        Some(_) => {}
+                        None => last_ele = Some(ele),
+                    }
+                }
+                if let Some(t) = p.time.as_deref().and_then(parse_rfc3339) {
+                    first_time.get_or_insert(t);
+                    last_time = Some(t);
+                }
+            }
+        }
+        if let (Some(first), Some(last)) = (first_time, last_time) {
+            stats.duration_s = Some(last - first);
+        }
+        stats
+    }
+}
+
+pub fn parse_gpx(text: &str) -> anyhow::Result<Gpx> {
+    let doc = roxmltree::Document::parse(text)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "gpx" {
+        anyhow::bail!(
+            "not a GPX file: root element is <{}>",
+            root.tag_name().name()
+        );
+    }
+    let mut gpx = Gpx::default();
+    for node in root.children().filter(|n| n.is_element()) {
+        match node.tag_name().name() {
+            "wpt" => gpx.waypoints.push(parse_point(node)?),
+            "rte" => gpx.routes.push(GpxPath {
+                name: child_text(node, "name"),
+                segments: vec![children(node, "rtept")
+                    .map(parse_point)
+                    .collect::<anyhow::Result<_>>()?],
+            }),
+            "trk" => gpx.tracks.push(GpxPath {
+                name: child_text(node, "name"),
+                segments: children(node, "trkseg")
+                    .map(|seg| children(seg, "trkpt").map(parse_point).collect())
+                    .collect::<anyhow::Result<_>>()?,
+            }),
+            _ => {}
+        }
+    }
+    Ok(gpx)
+}
+
+fn children<'a, 'input: 'a>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn child_text(node: roxmltree::Node, name: &str) -> Option<String> {
+    children(node, name)
+        .next()
+        .and_then(|n| n.text())
+        .map(|t| t.trim().to_string())
+}
+
+fn parse_point(node: roxmltree::Node) -> anyhow::Result<GpxPoint> {
+    let coord = |name: &str| -> anyhow::Result<f64> {
+        let value = node
+            .attribute(name)
+            .ok_or_else(|| anyhow::anyhow!("<{}> without {name}", node.tag_name().name()))?;
+        Ok(value.trim().parse()?)
+    };
+    Ok(GpxPoint {
+        lat: coord("lat")?,
+        lon: coord("lon")?,
+        ele: child_text(node, "ele").and_then(|e| e.parse().ok()),
+        time: child_text(node, "time"),
+        name: child_text(node, "name"),
+    })
+}
+
+/// Seconds since the Unix epoch of an RFC 3339 timestamp, as GPX writes them:
+/// `2024-05-01T08:30:00Z`, with optional fractional seconds and `+hh:mm` offset.
+pub fn parse_rfc3339(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let (date, time) = text.split_once(['T', 't', ' '])?;
+    let mut date = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+
+    let (time, offset_s) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else {
+        let split = time.rfind(['+', '-'])?;
+        let (time, offset) = time.split_at(split);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let (oh, om) = offset[1..].split_once(':')?;
+        (
+            time,
+            sign * (oh.parse::<i64>().ok()? * 3600 + om.parse::<i64>().ok()? * 60),
+        )
+    };
+    let mut hms = time.splitn(3, ':');
+    let hour = hms.next()?.parse::<i64>().ok()?;
+    let minute = hms.next()?.parse::<i64>().ok()?;
+    let second = hms.next()?.parse::<f64>().ok()?;
+
+    let days = days_from_civil(year, month, day);
+    Some((days * 86_400 + hour * 3600 + minute * 60 - offset_s) as f64 + second)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's algorithm).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_point(out: &mut String, tag: &str, p: &GpxPoint, indent: &str) {
+    out.push_str(&format!(
+        "{indent}<{tag} lat=\"{:.7}\" lon=\"{:.7}\">",
+        p.lat, p.lon
+    ));
+    if let Some(ele) = p.ele {
+        out.push_str(&format!("<ele>{ele}</ele>"));
+    }
+    if let Some(time) = &p.time {
+        out.push_str(&format!("<time>{}</time>", escape_xml(time)));
+    }
+    if let Some(name) = &p.name {
+        out.push_str(&format!("<name>{}</name>", escape_xml(name)));
+    }
+    out.push_str(&format!("</{tag}>\n"));
+}
+
+impl Gpx {
+    pub fn to_gpx_string(&self) -> String {
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str(&format!(
+            "<gpx version=\"1.1\" creator=\"ESCAPE_FROM_FERENTAR\" xmlns=\"{GPX_NS}\">\n"
+        ));
+        for p in self.waypoints.iter() {
+            write_point(&mut out, "wpt", p, "  ");
+        }
+        for route in self.routes.iter() {
+            out.push_str("  <rte>\n");
+            if let Some(name) = &route.name {
+                out.push_str(&format!("    <name>{}</name>\n", escape_xml(name)));
+            }
+            for p in route.segments.iter().flatten() {
+                write_point(&mut out, "rtept", p, "    ");
+            }
+            out.push_str("  </rte>\n");
+        }
+        for track in self.tracks.iter() {
+            out.push_str("  <trk>\n");
+            if let Some(name) = &track.name {
+                out.push_str(&format!("    <name>{}</name>\n", escape_xml(name)));
+            }
+            for segment in track.segments.iter() {
+                out.push_str("    <trkseg>\n");
+                for p in segment.iter() {
+                    write_point(&mut out, "trkpt", p, "      ");
+                }
+                out.push_str("    </trkseg>\n");
+            }
+            out.push_str("  </trk>\n");
+        }
+        out.push_str("</gpx>\n");
+        out
+    }
+
+    pub fn to_vector_layer(&self, name: &str) -> VectorLayer {
+        let mut features = vec![];
+        for (kind, path) in self
+            .routes
+            .iter()
+            .map(|r| ("route", r))
+            .chain(self.tracks.iter().map(|t| ("track", t)))
+        {
+            let stats = path.stats();
+            let mut properties = vec![("type".to_string(), kind.to_string())];
+            if let Some(name) = &path.name {
+                properties.push(("name".to_string(), name.clone()));
+            }
+            properties.push((
+                "distance".to_string(),
+                format!("{:.2} km", stats.distance_m / 1000.0),
+            ));
+            properties.push((
+                "elevation gain".to_string(),
+                format!("{:.0} m", stats.elevation_gain_m),
+            ));
+            properties.push((
+                "elevation loss".to_string(),
+                format!("{:.0} m", stats.elevation_loss_m),
+            ));
+            if let Some(duration) = stats.duration_s {
+                let minutes = (duration / 60.0).round() as i64;
+                properties.push((
+                    "time".to_string(),
+                    format!("{}h {:02}m", minutes / 60, minutes % 60),
+                ));
+            }
+            features.push(Feature {
+                geometries: path
+                    .segments
+                    .iter()
+                    .map(|s| Geometry::LineString(s.iter().map(|p| (p.lat, p.lon)).collect()))
+                    .collect(),
+                properties,
+                style: FeatureStyle {
+                    stroke: "#e0245e".to_string(),
+                    stroke_width: 3.0,
+                    ..FeatureStyle::default()
+                },
+            });
+        }
+        for p in self.waypoints.iter() {
+            let mut properties = vec![("type".to_string(), "waypoint".to_string())];
+            if let Some(name) = &p.name {
+                properties.push(("name".to_string(), name.clone()));
+            }
+            if let Some(ele) = p.ele {
+                properties.push(("elevation".to_string(), format!("{ele:.0} m")));
+            }
+            features.push(Feature {
+                geometries: vec![Geometry::Point((p.lat, p.lon))],
+                properties,
+                style: FeatureStyle::default(),
+            });
+        }
+        VectorLayer {
+            name: name.to_string(),
+            features,
+            gpx: Some(self.clone()),
+            ground_overlays: vec![],
+        }
+    }
+
+    /// Lines become tracks and points become waypoints; polygons are skipped.
+    /// Used for layers that were not loaded from a GPX file.
+    pub fn from_vector_layer(layer: &VectorLayer) -> Self {
+        if let Some(gpx) = &layer.gpx {
+            return gpx.clone();
+        }
+        let mut gpx = Gpx::default();
+        for feature in layer.features.iter() {
+            let name = feature
+                .properties
+                .iter()
+                .find(|(k, _)| k == "name")
+                .map(|(_, v)| v.clone());
+            let mut segments = vec![];
+            for geometry in feature.geometries.iter() {
+                match geometry {
+                    Geometry::Point((lat, lon)) => gpx.waypoints.push(GpxPoint {
+                        lat: *lat,
+                        lon: *lon,
+                        name: name.clone(),
+                        ..GpxPoint::default()
+                    }),
+                    Geometry::LineString(line) => segments.push(
+                        line.iter()
+                            .map(|(lat, lon)| GpxPoint {
+                                lat: *lat,
+                                lon: *lon,
+                                ..GpxPoint::default()
+                            })
+                            .collect(),
+                    ),
+                    Geometry::Polygon(_) => {}
+                }
+            }
+            if !segments.is_empty() {
+                gpx.tracks.push(GpxPath { name, segments });
+            }
+        }
+        gpx
+    }
+
+    /// The measured points as a single route, back to the first point for a polygon.
+    pub fn from_measurement(measurement: &Measurement, name: &str) -> Self {
+        let mut points = measurement.points.clone();
+        if let (true, Some(first)) = (measurement.closed && points.len() > 2, points.first()) {
+            points.push(*first);
+        }
+        let points = points
+            .into_iter()
+            .map(|(lat, lon)| GpxPoint {
+                lat,
+                lon,
+                ..GpxPoint::default()
+            })
+            .collect();
+        Gpx {
+            routes: vec![GpxPath {
+                name: Some(name.to_string()),
+                segments: vec![points],
+            }],
+            ..Gpx::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1">
+  <wpt lat="44.4268" lon="26.1025"><ele>80</ele><name>Piata Unirii &amp; co</name></wpt>
+  <rte>
+    <name>route</name>
+    <rtept lat="44.0" lon="26.0"/>
+    <rtept lat="44.0" lon="26.1"/>
+  </rte>
+  <trk>
+    <name>hike</name>
+    <trkseg>
+      <trkpt lat="45.0" lon="25.0"><ele>1000</ele><time>2024-05-01T08:00:00Z</time></trkpt>
+      <trkpt lat="45.001" lon="25.0"><ele>1001</ele><time>2024-05-01T08:10:00Z</time></trkpt>
+      <trkpt lat="45.002" lon="25.0"><ele>1050</ele><time>2024-05-01T08:20:00Z</time></trkpt>
+    </trkseg>
+    <trkseg>
+      <trkpt lat="45.010" lon="25.0"><ele>1020</ele><time>2024-05-01T09:30:00.5+01:00</time></trkpt>
+    </trkseg>
+  </trk>
+</gpx>"#;
+
+    #[test]
+    fn parses_waypoints_routes_and_tracks() {
+        let gpx = parse_gpx(SAMPLE).unwrap();
+        assert_eq!(gpx.waypoints.len(), 1);
+        assert_eq!(gpx.waypoints[0].name.as_deref(), Some("Piata Unirii & co"));
+        assert_eq!(gpx.waypoints[0].ele, Some(80.0));
+        assert_eq!(gpx.routes.len(), 1);
+        assert_eq!(gpx.routes[0].segments[0].len(), 2);
+        assert_eq!(gpx.tracks.len(), 1);
+        assert_eq!(gpx.tracks[0].name.as_deref(), Some("hike"));
+        assert_eq!(gpx.tracks[0].segments.len(), 2);
+        assert_eq!(gpx.tracks[0].segments[0][2].ele, Some(1050.0));
+    }
+
+    #[test]
+    fn rejects_other_xml() {
+        assert!(parse_gpx("<kml></kml>").is_err());
+        assert!(parse_gpx("not xml").is_err());
+        assert!(parse_gpx(r#"<gpx><wpt lon="1"/></gpx>"#).is_err());
+    }
+
+    #[test]
+    fn track_stats() {
+        let gpx = parse_gpx(SAMPLE).unwrap();
+        let stats = gpx.tracks[0].stats();
+        // two steps of 0.001° of latitude, about 111 m each; segments are not joined
+        assert!(
+            (stats.distance_m - 222.4).abs() < 1.0,
+            "{}",
+            stats.distance_m
+        );
+        // +1 m is noise, +50 m counts; the second segment starts a new baseline
+        assert_eq!(stats.elevation_gain_m, 50.0);
+        assert_eq!(stats.elevation_loss_m, 0.0);
+        // 09:30:00.5+01:00 is 08:30:00.5Z
+        assert_eq!(stats.duration_s, Some(1800.5));
+    }
+
+    #[test]
+    fn route_distance() {
+        let gpx = parse_gpx(SAMPLE).unwrap();
+        let stats = gpx.routes[0].stats();
+        // 0.1° of longitude at 44°N
+        assert!(
+            (stats.distance_m - 7_999.0).abs() < 20.0,
+            "{}",
+            stats.distance_m
+        );
+        assert_eq!(stats.duration_s, None);
+    }
+
+    #[test]
+    fn rfc3339() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0.0));
+        assert_eq!(parse_rfc3339("2000-03-01T00:00:00Z"), Some(951_868_800.0));
+        assert_eq!(
+            parse_rfc3339("2024-05-01T10:00:00+02:00"),
+            parse_rfc3339("2024-05-01T08:00:00Z")
+        );
+        assert_eq!(parse_rfc3339("yesterday"), None);
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let gpx = parse_gpx(SAMPLE).unwrap();
+        let text = gpx.to_gpx_string();
+        assert!(text.contains("Piata Unirii &amp; co"));
+        assert_eq!(parse_gpx(&text).unwrap(), gpx);
+    }
+
+    #[test]
+    fn vector_layer_round_trip() {
+        let gpx = parse_gpx(SAMPLE).unwrap();
+        let layer = gpx.to_vector_layer("hike.gpx");
+        assert_eq!(layer.features.len(), 3);
+        assert_eq!(Gpx::from_vector_layer(&layer), gpx);
+
+        let drawn = VectorLayer { gpx: None, ..layer };
+        let exported = Gpx::from_vector_layer(&drawn);
+        assert_eq!(exported.waypoints.len(), 1);
+        assert_eq!(exported.tracks.len(), 2);
+        assert_eq!(exported.tracks[1].segments.len(), 2);
+    }
+
+    #[test]
+    fn measurement_exports_as_route() {
+        let mut measurement = Measurement {
+            points: vec![(44.0, 26.0), (44.0, 26.1), (44.1, 26.1)],
+            closed: false,
+        };
+        let gpx =
+            parse_gpx(&Gpx::from_measurement(&measurement, "measurement").to_gpx_string()).unwrap();
+        assert!(gpx.tracks.is_empty() && gpx.waypoints.is_empty());
+        assert_eq!(gpx.routes[0].name.as_deref(), Some("measurement"));
+        let route = &gpx.routes[0].segments[0];
+        assert_eq!(route.len(), 3);
+        assert_eq!((route[2].lat, route[2].lon), (44.1, 26.1));
+        let open_length = gpx.routes[0].stats().distance_m;
+        assert!((open_length - measurement.total_length_m()).abs() < 50.0);
+
+        measurement.closed = true;
+        let route = &Gpx::from_measurement(&measurement, "measurement").routes[0].segments[0];
+        assert_eq!(route.len(), 4);
+        assert_eq!((route[3].lat, route[3].lon), (44.0, 26.0));
+    }
+}
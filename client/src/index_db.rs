@@ -4,9 +4,13 @@ use indexed_db_futures::database::Database;
 use indexed_db_futures::prelude::*;
 use indexed_db_futures::transaction::TransactionMode;
 
+use crate::data_loader::TileKey;
+use crate::markers::Marker;
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct ImageCacheRow {
-    pub id: [i32; 3],
+    /// `(layer id, z, x, y)`
+    pub id: TileKey,
     pub img_b64: String,
 }
 
@@ -24,14 +28,14 @@ pub fn init_db_globals() {
     // let _ = db_res.read();
 }
 
-pub async fn read_image(key: (i32, i32, i32)) -> anyhow::Result<Option<ImageCacheRow>> {
+pub async fn read_image(key: TileKey) -> anyhow::Result<Option<ImageCacheRow>> {
     let db_res = use_context::<DbReesource>();
     let db = match db_res.peek().as_ref() {
         None => anyhow::bail!("read_image(): db not connected yet."),
         Some(Err(e)) => anyhow::bail!("read_image(): db connection error: {:?}", e),
         Some(Ok(db)) => db.clone(),
     };
-    match _do_read_image(&db, [key.0, key.1, key.2]).await {
+    match _do_read_image(&db, key).await {
         Ok(i) => Ok(i),
         Err(e) => anyhow::bail!(
             "read_image(): error fetching img from local storage: {:?}",
@@ -39,14 +43,14 @@ pub async fn read_image(key: (i32, i32, i32)) -> anyhow::Result<Option<ImageCach
         ),
     }
 }
-pub async fn write_image(key: (i32, i32, i32), val: &str) -> anyhow::Result<()> {
+pub async fn write_image(key: TileKey, val: &str) -> anyhow::Result<()> {
     let db_res = use_context::<DbReesource>();
     let db = match db_res.peek().as_ref() {
         None => anyhow::bail!("write_image(): db not connected yet."),
         Some(Err(e)) => anyhow::bail!("write_image(): db connection error: {:?}", e),
         Some(Ok(db)) => db.clone(),
     };
-    match _do_write_image(&db, [key.0, key.1, key.2], val).await {
+    match _do_write_image(&db, key, val).await {
         Ok(_) => Ok(()),
         Err(e) => anyhow::bail!(
             "write_image(): error writing img into local storage: {:?}",
@@ -55,18 +59,82 @@ pub async fn write_image(key: (i32, i32, i32), val: &str) -> anyhow::Result<()>
     }
 }
 
+pub async fn read_markers() -> anyhow::Result<Vec<Marker>> {
+    let db_res = use_context::<DbReesource>();
+    let db = match db_res.peek().as_ref() {
+        None => anyhow::bail!("read_markers(): db not connected yet."),
+        Some(Err(e)) => anyhow::bail!("read_markers(): db connection error: {:?}", e),
+        Some(Ok(db)) => db.clone(),
+    };
+    match _do_read_markers(&db).await {
+        Ok(m) => Ok(m),
+        Err(e) => anyhow::bail!("read_markers(): error reading markers: {:?}", e),
+    }
+}
+
+pub async fn write_marker(marker: &Marker) -> anyhow::Result<()> {
+    let db_res = use_context::<DbReesource>();
+    let db = match db_res.peek().as_ref() {
+        None => anyhow::bail!("write_marker(): db not connected yet."),
+        Some(Err(e)) => anyhow::bail!("write_marker(): db connection error: {:?}", e),
+        Some(Ok(db)) => db.clone(),
+    };
+    match _do_write_marker(&db, marker).await {
+        Ok(_) => Ok(()),
+        Err(e) => anyhow::bail!("write_marker(): error writing marker: {:?}", e),
+    }
+}
+
+pub async fn delete_marker(id: String) -> anyhow::Result<()> {
+    let db_res = use_context::<DbReesource>();
+    let db = match db_res.peek().as_ref() {
+        None => anyhow::bail!("delete_marker(): db not connected yet."),
+        Some(Err(e)) => anyhow::bail!("delete_marker(): db connection error: {:?}", e),
+        Some(Ok(db)) => db.clone(),
+    };
+    match _do_delete_marker(&db, id).await {
+        Ok(_) => Ok(()),
+        Err(e) => anyhow::bail!("delete_marker(): error deleting marker: {:?}", e),
+    }
+}
+
+fn create_tile_store(db: &Database) -> indexed_db_futures::Result<()> {
+    db.create_object_store("tile_store")
+        .with_auto_increment(false)
+        .with_key_path(indexed_db_futures::KeyPath::One("id"))
+        .build()?;
+    Ok(())
+}
+
+fn create_marker_store(db: &Database) -> indexed_db_futures::Result<()> {
+    db.create_object_store("marker_store")
+        .with_auto_increment(false)
+        .with_key_path(indexed_db_futures::KeyPath::One("id"))
+        .build()?;
+    Ok(())
+}
+
 async fn _do_init_db() -> Result<Database, String> {
     info!("_do_init_db(): starting...");
     let db = Database::open("image_db4")
-        .with_version(1u8)
+        .with_version(3u8)
         .with_on_upgrade_needed(|event, db| {
             match (event.old_version(), event.new_version()) {
-                (0.0, Some(1.0)) => {
-                    info!("_do_init_db(): creating object store 'image_store'...");
-                    db.create_object_store("image_store")
-                        .with_auto_increment(false)
-                        .with_key_path(indexed_db_futures::KeyPath::One("id"))
-                        .build()?;
+                (0.0, Some(3.0)) => {
+                    info!("_do_init_db(): creating object stores 'tile_store', 'marker_store'...");
+                    create_tile_store(db)?;
+                    create_marker_store(db)?;
+                }
+                (1.0, Some(3.0)) => {
+                    // v1 keyed images by (z, x, y) only, without the layer id.
+                    info!("_do_init_db(): replacing 'image_store' with 'tile_store'...");
+                    db.delete_object_store("image_store")?;
+                    create_tile_store(db)?;
+                    create_marker_store(db)?;
+                }
+                (2.0, Some(3.0)) => {
+                    info!("_do_init_db(): creating object store 'marker_store'...");
+                    create_marker_store(db)?;
                 }
                 _ => {
                     error!("_do_init_db(): error upgrading localdb.");
@@ -87,16 +155,16 @@ async fn _do_init_db() -> Result<Database, String> {
 
 async fn _do_write_image(
     db: &Database,
-    key: [i32; 3],
+    key: TileKey,
     val: &str,
 ) -> indexed_db_futures::OpenDbResult<()> {
     // Populate some data
     let transaction = db
-        .transaction("image_store")
+        .transaction("tile_store")
         .with_mode(TransactionMode::Readwrite)
         .build()?;
 
-    let store = transaction.object_store("image_store")?;
+    let store = transaction.object_store("tile_store")?;
 
     // awaiting individual requests is optional - they still go out
     store
@@ -114,17 +182,60 @@ async fn _do_write_image(
 
 async fn _do_read_image(
     db: &Database,
-    key: [i32; 3],
+    key: TileKey,
 ) -> indexed_db_futures::OpenDbResult<Option<ImageCacheRow>> {
     // Populate some data
     let transaction = db
-        .transaction("image_store")
+        .transaction("tile_store")
         .with_mode(TransactionMode::Readonly)
         .build()?;
 
-    let store = transaction.object_store("image_store")?;
+    let store = transaction.object_store("tile_store")?;
 
     // awaiting individual requests is optional - they still go out
 
     Ok(store.get(key).serde()?.await?)
 }
+
+async fn _do_read_markers(db: &Database) -> indexed_db_futures::OpenDbResult<Vec<Marker>> {
+    let transaction = db
+        .transaction("marker_store")
+        .with_mode(TransactionMode::Readonly)
+        .build()?;
+
+    let store = transaction.object_store("marker_store")?;
+
+    let markers = store
+        .get_all::<Marker>()
+        .serde()?
+        .await?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(markers)
+}
+
+async fn _do_write_marker(db: &Database, marker: &Marker) -> indexed_db_futures::OpenDbResult<()> {
+    let transaction = db
+        .transaction("marker_store")
+        .with_mode(TransactionMode::Readwrite)
+        .build()?;
+
+    let store = transaction.object_store("marker_store")?;
+    store.put(marker.clone()).serde()?;
+
+    // Unlike JS, transactions ROLL BACK INSTEAD OF COMMITTING BY DEFAULT
+    transaction.commit().await?;
+    Ok(())
+}
+
+async fn _do_delete_marker(db: &Database, id: String) -> indexed_db_futures::OpenDbResult<()> {
+    let transaction = db
+        .transaction("marker_store")
+        .with_mode(TransactionMode::Readwrite)
+        .build()?;
+
+    let store = transaction.object_store("marker_store")?;
+    store.delete(id).serde()?;
+
+    transaction.commit().await?;
+    Ok(())
+}
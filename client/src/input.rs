@@ -1,19 +1,101 @@
 #[allow(non_snake_case)]
 use dioxus::prelude::*;
 use dioxus_elements::geometry::WheelDelta;
+use dioxus_logger::tracing::warn;
 
-use crate::{_const::{MAX_Z, MIN_Z}, url_state::MapState};
+use std::collections::HashSet;
+
+use crate::{
+    _const::{MAX_Z, MIN_Z},
+    animation::{fly_to, VelocityTracker},
+    geometry::{clamp_world_pos, rotate, screen_to_world},
+    url_state::{MapState, INIT_STATE},
+};
+
+/// fling speed decays by `e` every this many seconds
+const FLING_TIME_CONSTANT: f64 = 0.325;
+/// px/s under which a fling stops, or does not start at all
+const FLING_MIN_SPEED: f64 = 30.0;
+/// how long a finger has to stay down for a long press
+const LONG_PRESS_TIME: std::time::Duration = std::time::Duration::from_millis(600);
+/// px a finger may wander during a long press
+const LONG_PRESS_SLOP: f64 = 10.0;
+/// px the pointer may move between press and release for it to still count as a click
+const CLICK_SLOP: f64 = 5.0;
+/// held arrow keys pan this many tiles per second; a tile is half the smaller screen side
+const KEY_PAN_SPEED: f64 = 1.0;
+/// held `+`/`-` zoom this many levels per second
+const KEY_ZOOM_SPEED: f64 = 2.0;
+/// Shift multiplies the pan speed by this; zoom keys ignore it, `+` needs Shift on most layouts
+const KEY_FAST_FACTOR: f64 = 3.0;
+/// how long the Home key takes to fly back to the start
+const KEY_HOME_FLY_DURATION: std::time::Duration = std::time::Duration::from_millis(1000);
+
+/// What a held key does to the map.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum KeyMove {
+    Left,
+    Right,
+    Up,
+    Down,
+    ZoomIn,
+    ZoomOut,
+}
+
+impl KeyMove {
+    /// Arrows and WASD pan; the `=`/`+` and `-` keys, main or numpad, zoom.
+    fn from_key(key: &Key, code: Code) -> Option<Self> {
+        match code {
+            Code::Equal | Code::NumpadAdd => return Some(Self::ZoomIn),
+            Code::Minus | Code::NumpadSubtract => return Some(Self::ZoomOut),
+            _ => {}
+        }
+        match key {
+            Key::ArrowLeft => Some(Self::Left),
+            Key::ArrowRight => Some(Self::Right),
+            Key::ArrowUp => Some(Self::Up),
+            Key::ArrowDown => Some(Self::Down),
+            Key::Character(c) => match c.to_lowercase().as_str() {
+                "a" => Some(Self::Left),
+                "d" => Some(Self::Right),
+                "w" => Some(Self::Up),
+                "s" => Some(Self::Down),
+                "+" | "=" => Some(Self::ZoomIn),
+                "-" | "_" => Some(Self::ZoomOut),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
 
 #[component]
 pub fn MapsController(
     mut map_state: Signal<MapState>,
     mut dimensions: Signal<(f64, f64)>,
+    /// `window.devicePixelRatio`, read on mount and on every resize (browser zoom changes it)
+    mut device_pixel_ratio: Signal<f64>,
+    /// page coordinates of the mouse or of the touching finger, `None` when off the map
+    mut cursor_pos: Signal<Option<(f64, f64)>>,
+    /// the running `animation::fly_to`, cancelled when the user takes over
+    mut flight: Signal<Option<Task>>,
+    /// long press or right click, with the world point under the pointer
+    on_long_press: EventHandler<(f64, f64)>,
+    /// press and release without dragging, with the world point under the pointer
+    on_click: EventHandler<(f64, f64)>,
+    /// `(file name, contents)` of each file dropped on the map
+    on_file_drop: EventHandler<(String, Vec<u8>)>,
 ) -> Element {
     #[derive(Copy, Clone, Debug)]
     struct PointerMoveEvent {
         coord_x: f64,
         coord_y: f64,
         is_pressed: bool,
+        /// a release with this set starts a fling or counts as a click;
+        /// unset when a second finger lands
+        can_fling: bool,
+        /// Shift+drag turns the map around the screen center instead of panning
+        rotate_map: bool,
     }
 
     #[derive(Copy, Clone, Debug)]
@@ -22,9 +104,68 @@ pub fn MapsController(
         pixels_diff: f64,
         pinch_dist: f64,
         is_pinch: bool,
+        /// page coordinates of the cursor or of the middle between the pinch fingers
+        center_x: f64,
+        center_y: f64,
     }
 
     let mut last_pointer_pos: Signal<Option<(f64, f64)>> = use_signal(|| None);
+    // where the current press started, while it can still become a click
+    let mut press_start: Signal<Option<(f64, f64)>> = use_signal(|| None);
+    let mut velocity = use_signal(VelocityTracker::default);
+    let mut receiver: Signal<Option<std::rc::Rc<MountedData>>> = use_signal(|| None);
+    let mut fling_task: Signal<Option<Task>> = use_signal(|| None);
+
+    let mut start_fling = move |(vx, vy): (f64, f64)| {
+        if vx.hypot(vy) < FLING_MIN_SPEED {
+            return;
+        }
+        let task = spawn(async move {
+            let mut v = (vx, vy);
+            let mut last_frame = web_time::Instant::now();
+            while v.0.hypot(v.1) >= FLING_MIN_SPEED {
+                let dt = crate::animation::next_frame(&mut last_frame).await;
+                let dims = *dimensions.peek();
+                let quad_edge = f64::min(dims.0, dims.1) / 2.0;
+                let exp = f64::exp2(crate::_const::REF_Z - map_state.peek().zoom);
+                let old_pos = map_state.peek().pos;
+                let step = rotate((v.0 * dt, v.1 * dt), map_state.peek().bearing);
+                map_state.write().pos = clamp_world_pos((
+                    old_pos.0 - step.0 / quad_edge * exp,
+                    old_pos.1 - step.1 / quad_edge * exp,
+                ));
+                let decay = f64::exp(-dt / FLING_TIME_CONSTANT);
+                v = (v.0 * decay, v.1 * decay);
+            }
+            fling_task.set(None);
+        });
+        fling_task.set(Some(task));
+    };
+
+    let press_to_world = move |page: (f64, f64)| {
+        let state = map_state.peek();
+        screen_to_world(state.pos, state.zoom, state.bearing, *dimensions.peek(), page)
+    };
+
+    // turns the map clockwise by `degrees` around the page point `center`
+    let mut on_rotate = move |degrees: f64, center: (f64, f64)| {
+        if degrees.abs() < 0.00001 {
+            return;
+        }
+        let (pos, zoom, bearing) = {
+            let state = map_state.peek();
+            (state.pos, state.zoom, state.bearing)
+        };
+        let dims = *dimensions.peek();
+        let anchor = screen_to_world(pos, zoom, bearing, dims, center);
+        let new_bearing = (bearing - degrees).rem_euclid(360.0);
+        // where `center` lands relative to the map center after turning
+        let offset = screen_to_world((0.0, 0.0), zoom, new_bearing, dims, center);
+        let mut state = map_state.write();
+        state.bearing = new_bearing;
+        state.pos = clamp_world_pos((anchor.0 - offset.0, anchor.1 - offset.1));
+    };
+
     let mut on_movement = move |event: PointerMoveEvent| {
         let last = *last_pointer_pos.peek();
         let current = if event.is_pressed {
@@ -35,16 +176,57 @@ pub fn MapsController(
         let quad_edge = *dimensions.peek();
         let quad_edge = f64::min(quad_edge.0, quad_edge.1) / 2.0;
 
+        // a new press stops the map where it is
+        if let (Some(current), None) = (current, last) {
+            if let Some(task) = fling_task.take() {
+                task.cancel();
+            }
+            if let Some(task) = flight.take() {
+                task.cancel();
+            }
+            velocity.write().clear();
+            velocity.write().push(current);
+            press_start.set(Some(current));
+        }
+        if let (None, Some(last)) = (current, last) {
+            let v = velocity.peek().velocity();
+            velocity.write().clear();
+            let start = press_start.take();
+            if event.can_fling {
+                start_fling(v);
+                if start.is_some() {
+                    on_click.call(press_to_world(last));
+                }
+            }
+        }
+
         if let (Some(current), Some(last)) = (current, last) {
-            let diff = (
-                (current.0 - last.0) / quad_edge,
-                (current.1 - last.1) / quad_edge,
-            );
-            if diff.0.abs() + diff.1.abs() > 0.00001 {
-                // warn!("MOVEMENT DIFF = {diff:?}");
-                let old_pos = map_state.peek().pos;
-                let exp = f64::exp2(crate::_const::REF_Z - map_state.peek().zoom);
-                map_state.write().pos = (old_pos.0 - diff.0 * exp, old_pos.1 - diff.1 * exp);
+            let start = *press_start.peek();
+            if start.is_some_and(|s| (current.0 - s.0).hypot(current.1 - s.1) > CLICK_SLOP) {
+                press_start.set(None);
+            }
+            if event.rotate_map {
+                let dims = *dimensions.peek();
+                let center = (dims.0 / 2.0, dims.1 / 2.0);
+                let angle = |p: (f64, f64)| f64::atan2(p.1 - center.1, p.0 - center.0).to_degrees();
+                let turn = (angle(current) - angle(last) + 180.0).rem_euclid(360.0) - 180.0;
+                on_rotate(turn, center);
+            } else {
+                velocity.write().push(current);
+                let diff = rotate(
+                    (
+                        (current.0 - last.0) / quad_edge,
+                        (current.1 - last.1) / quad_edge,
+                    ),
+                    map_state.peek().bearing,
+                );
+                if diff.0.abs() + diff.1.abs() > 0.00001 {
+                    // warn!("MOVEMENT DIFF = {diff:?}");
+                    let old_pos = map_state.peek().pos;
+                    let exp = f64::exp2(crate::_const::REF_Z - map_state.peek().zoom);
+                    map_state.write().pos =
+                        clamp_world_pos((old_pos.0 - diff.0 * exp, old_pos.1 - diff.1 * exp));
+                }
             }
         }
 
@@ -54,6 +236,8 @@ pub fn MapsController(
     };
 
     let mut last_pinch_dist: Signal<Option<f64>> = use_signal(|| None);
+    // degrees of the line from the first to the second finger
+    let mut last_pinch_angle: Signal<Option<f64>> = use_signal(|| None);
     let mut on_zoom = move |event: MouseZoomEvent| {
         let last = *last_pinch_dist.peek();
         let current = if event.is_pinch {
@@ -82,9 +266,34 @@ pub fn MapsController(
 
         let diff = -diff_wheel + diff_pinch;
         if diff.abs() > 0.00001 {
+            if let Some(task) = flight.take() {
+                task.cancel();
+            }
             // warn!("ZOOM = {diff}");
             let _old_zoom_sig = map_state.peek().zoom;
-            map_state.write().zoom = (_old_zoom_sig + diff).clamp(MIN_Z as f64, MAX_Z as f64);
+            let new_zoom = (_old_zoom_sig + diff).clamp(MIN_Z as f64, MAX_Z as f64);
+
+            // keep the world point under the cursor in the same place on screen
+            let dims = *dimensions.peek();
+            let quad_edge = f64::min(dims.0, dims.1) / 2.0;
+            let anchor = rotate(
+                (
+                    (event.center_x - dims.0 / 2.0) / quad_edge,
+                    (event.center_y - dims.1 / 2.0) / quad_edge,
+                ),
+                map_state.peek().bearing,
+            );
+            let old_exp = f64::exp2(crate::_const::REF_Z - _old_zoom_sig);
+            let new_exp = f64::exp2(crate::_const::REF_Z - new_zoom);
+            let old_pos = map_state.peek().pos;
+            let new_pos = (
+                old_pos.0 + anchor.0 * (old_exp - new_exp),
+                old_pos.1 + anchor.1 * (old_exp - new_exp),
+            );
+
+            let mut state = map_state.write();
+            state.zoom = new_zoom;
+            state.pos = clamp_world_pos(new_pos);
         }
 
         if last != current {
@@ -95,19 +304,34 @@ pub fn MapsController(
     let on_mouse = move |event: Event<MouseData>| {
         event.prevent_default();
         let data = event.data();
+        let coords = data.page_coordinates();
+        cursor_pos.set(Some((coords.x, coords.y)));
+        let is_pressed = data
+            .held_buttons()
+            .contains(dioxus_elements::input_data::MouseButton::Primary);
+
+        // prevent_default() also keeps the browser from focusing the map on click,
+        // and the keyboard controls need the focus
+        if is_pressed && last_pointer_pos.peek().is_none() {
+            if let Some(receiver) = receiver.peek().clone() {
+                spawn(async move {
+                    let _ = receiver.set_focus(true).await;
+                });
+            }
+        }
 
         let ev = PointerMoveEvent {
-            coord_x: data.page_coordinates().x,
-            coord_y: data.page_coordinates().y,
-            is_pressed: data
-                .held_buttons()
-                .contains(dioxus_elements::input_data::MouseButton::Primary),
+            coord_x: coords.x,
+            coord_y: coords.y,
+            is_pressed,
+            can_fling: true,
+            rotate_map: data.modifiers().shift(),
         };
 
         on_movement(ev);
     };
 
-    let on_touch = move |event: Event<TouchData>| {
+    let mut on_touch = move |event: Event<TouchData>| {
         event.prevent_default();
         let data = event.data();
         let _changed = data.touches_changed();
@@ -124,10 +348,17 @@ pub fn MapsController(
             }
         };
 
+        cursor_pos.set(Some((
+            new_touch.page_coordinates().x,
+            new_touch.page_coordinates().y,
+        )));
+
         let ev = PointerMoveEvent {
             coord_x: new_touch.page_coordinates().x,
             coord_y: new_touch.page_coordinates().y,
             is_pressed: data.touches().len() == 1,
+            can_fling: data.touches().is_empty(),
+            rotate_map: false,
         };
 
         on_movement(ev);
@@ -141,6 +372,8 @@ pub fn MapsController(
                 lines_diff: 0.,
                 pinch_dist: touch_diff,
                 is_pinch: true,
+                center_x: (p1.x + p2.x) / 2.0,
+                center_y: (p1.y + p2.y) / 2.0,
             }
         } else {
             MouseZoomEvent {
@@ -148,9 +381,77 @@ pub fn MapsController(
                 lines_diff: 0.,
                 pinch_dist: 0.,
                 is_pinch: false,
+                center_x: 0.,
+                center_y: 0.,
             }
         };
         on_zoom(ev2);
+
+        // two fingers turning turn the map with them, around their middle
+        let angle = if _current.len() >= 2 {
+            let p1 = _current[0].page_coordinates();
+            let p2 = _current[1].page_coordinates();
+            let angle = f64::atan2(p2.y - p1.y, p2.x - p1.x).to_degrees();
+            if let Some(last) = *last_pinch_angle.peek() {
+                let turn = (angle - last + 180.0).rem_euclid(360.0) - 180.0;
+                on_rotate(turn, ((p1.x + p2.x) / 2.0, (p1.y + p2.y) / 2.0));
+            }
+            Some(angle)
+        } else {
+            None
+        };
+        if *last_pinch_angle.peek() != angle {
+            last_pinch_angle.set(angle);
+        }
+    };
+
+    let on_context_menu = move |event: Event<MouseData>| {
+        event.prevent_default();
+        let coords = event.data().page_coordinates();
+        on_long_press.call(press_to_world((coords.x, coords.y)));
+    };
+
+    // (where the finger went down, task that fires the long press)
+    let mut long_press: Signal<Option<((f64, f64), Task)>> = use_signal(|| None);
+    let mut cancel_long_press = move || {
+        if let Some((_, task)) = long_press.take() {
+            task.cancel();
+        }
+    };
+    let on_touch_start = move |event: Event<TouchData>| {
+        cancel_long_press();
+        let touches = event.data().touches();
+        if touches.len() == 1 {
+            let coords = touches[0].page_coordinates();
+            let start = (coords.x, coords.y);
+            let task = spawn(async move {
+                async_std::task::sleep(LONG_PRESS_TIME).await;
+                long_press.set(None);
+                // the finger lifting after a long press is not a click
+                press_start.set(None);
+                on_long_press.call(press_to_world(start));
+            });
+            long_press.set(Some((start, task)));
+        }
+        on_touch(event);
+    };
+    let on_touch_move = move |event: Event<TouchData>| {
+        let start = long_press.peek().as_ref().map(|(start, _)| *start);
+        if let Some(start) = start {
+            let touches = event.data().touches();
+            let moved = touches.first().map_or(true, |t| {
+                let coords = t.page_coordinates();
+                (coords.x - start.0).hypot(coords.y - start.1) > LONG_PRESS_SLOP
+            });
+            if moved || touches.len() != 1 {
+                cancel_long_press();
+            }
+        }
+        on_touch(event);
+    };
+    let on_touch_end = move |event: Event<TouchData>| {
+        cancel_long_press();
+        on_touch(event);
     };
 
     let on_wheel = move |event: Event<WheelData>| {
@@ -178,10 +479,101 @@ pub fn MapsController(
             pixels_diff,
             pinch_dist: 0.0,
             is_pinch: false,
+            center_x: data.page_coordinates().x,
+            center_y: data.page_coordinates().y,
         };
         on_zoom(ev);
     };
 
+    let read_pixel_ratio = move || {
+        spawn(async move {
+            match document::eval("return window.devicePixelRatio;").await {
+                Ok(ratio) => {
+                    if let Some(ratio) = ratio.as_f64().filter(|r| *r > 0.0) {
+                        if *device_pixel_ratio.peek() != ratio {
+                            device_pixel_ratio.set(ratio);
+                        }
+                    }
+                }
+                Err(e) => warn!("failed to read devicePixelRatio: {e:?}"),
+            }
+        });
+    };
+
+    let mut held_keys = use_signal(HashSet::<KeyMove>::new);
+    let mut keys_fast = use_signal(|| false);
+    let mut key_task: Signal<Option<Task>> = use_signal(|| None);
+    // moves the map every frame for as long as any key is held
+    let mut start_key_loop = move || {
+        if key_task.peek().is_some() {
+            return;
+        }
+        let task = spawn(async move {
+            let mut last_frame = web_time::Instant::now();
+            while !held_keys.peek().is_empty() {
+                let dt = crate::animation::next_frame(&mut last_frame).await;
+                let held = held_keys.peek().clone();
+                let is_held = |k: KeyMove| if held.contains(&k) { 1.0 } else { 0.0 };
+                let axis = |minus: KeyMove, plus: KeyMove| is_held(plus) - is_held(minus);
+                let speed = if *keys_fast.peek() { KEY_FAST_FACTOR } else { 1.0 } * dt;
+                let pan = (axis(KeyMove::Left, KeyMove::Right), axis(KeyMove::Up, KeyMove::Down));
+                let zoom_diff = axis(KeyMove::ZoomOut, KeyMove::ZoomIn) * KEY_ZOOM_SPEED * dt;
+
+                let old = map_state.peek().clone();
+                let pan = rotate(pan, old.bearing);
+                let exp = f64::exp2(crate::_const::REF_Z - old.zoom);
+                let mut state = map_state.write();
+                state.pos = clamp_world_pos((
+                    old.pos.0 + pan.0 * KEY_PAN_SPEED * speed * exp,
+                    old.pos.1 + pan.1 * KEY_PAN_SPEED * speed * exp,
+                ));
+                state.zoom = (old.zoom + zoom_diff).clamp(MIN_Z as f64, MAX_Z as f64);
+            }
+            key_task.set(None);
+        });
+        key_task.set(Some(task));
+    };
+
+    let on_key_down = move |event: Event<KeyboardData>| {
+        let key = event.data().key();
+        keys_fast.set(event.data().modifiers().shift());
+        if key == Key::Home {
+            event.prevent_default();
+            held_keys.write().clear();
+            // back to the start view, keeping the layers the user picked
+            let home = MapState {
+                pos: INIT_STATE.pos,
+                zoom: INIT_STATE.zoom,
+                bearing: INIT_STATE.bearing,
+                ..map_state.peek().clone()
+            };
+            fly_to(map_state, flight, home, KEY_HOME_FLY_DURATION);
+            return;
+        }
+        let Some(key_move) = KeyMove::from_key(&key, event.data().code()) else {
+            return;
+        };
+        // arrow keys would scroll the page
+        event.prevent_default();
+        if let Some(task) = flight.take() {
+            task.cancel();
+        }
+        if let Some(task) = fling_task.take() {
+            task.cancel();
+        }
+        if !held_keys.peek().contains(&key_move) {
+            held_keys.write().insert(key_move);
+        }
+        start_key_loop();
+    };
+
+    let on_key_up = move |event: Event<KeyboardData>| {
+        keys_fast.set(event.data().modifiers().shift());
+        if let Some(key_move) = KeyMove::from_key(&event.data().key(), event.data().code()) {
+            held_keys.write().remove(&key_move);
+        }
+    };
+
     rsx! {
         div {
             id: "receiver",
@@ -189,15 +581,37 @@ pub fn MapsController(
             onmousemove: on_mouse,
             onmousedown: on_mouse,
             onmouseup: on_mouse,
+            onmouseleave: move |_| cursor_pos.set(None),
+            oncontextmenu: on_context_menu,
+            // the browser only allows a drop if dragover is cancelled
+            ondragover: move |event| event.prevent_default(),
+            ondrop: move |event: Event<DragData>| async move {
+                event.prevent_default();
+                let Some(engine) = event.files() else {
+                    return;
+                };
+                for name in engine.files() {
+                    if let Some(bytes) = engine.read_file(&name).await {
+                        on_file_drop.call((name, bytes));
+                    }
+                }
+            },
             onwheel: on_wheel,
+            onkeydown: on_key_down,
+            onkeyup: on_key_up,
+            // keys released while the map was not focused never send a keyup
+            onblur: move |_| held_keys.write().clear(),
 
-            ontouchcancel: on_touch,
-            ontouchend: on_touch,
-            ontouchmove: on_touch,
-            ontouchstart: on_touch,
+            ontouchcancel: on_touch_end,
+            ontouchend: on_touch_end,
+            ontouchmove: on_touch_move,
+            ontouchstart: on_touch_start,
 
             // get initial mounted component size
             onmounted: move |event| async move {
+                receiver.set(Some(event.data()));
+                read_pixel_ratio();
+                let _ = event.set_focus(true).await;
                 if let Ok(client_rect) = event.get_client_rect().await {
                     let size = client_rect.size;
                     dimensions.set((size.width, size.height));
@@ -206,7 +620,8 @@ pub fn MapsController(
             // update component size
             onresize: move |event| {
                 let size = event.data().get_content_box_size().unwrap();
-                dimensions.set((size.width, size.height))
+                dimensions.set((size.width, size.height));
+                read_pixel_ratio();
             },
         }
     }
@@ -0,0 +1,409 @@
+//! KML and KMZ import: Placemarks with Point/LineString/Polygon/MultiGeometry,
+//! basic Style/StyleMap colors, and GroundOverlay images.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use roxmltree::Node;
+
+use crate::geometry::LatLonBounds;
+use crate::overlay::{Feature, FeatureStyle, Geometry, GroundOverlay, VectorLayer};
+use crate::tile_provider::TileImage;
+
+/// Reads a KMZ: the first `.kml` in the archive (`doc.kml` by convention), with
+/// GroundOverlay images taken from the other files in the archive.
+pub fn parse_kmz(name: &str, bytes: &[u8]) -> anyhow::Result<VectorLayer> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))?;
+    let mut kml_text = None;
+    let mut files = HashMap::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        let file_name = file.name().to_string();
+        let mut contents = vec![];
+        file.read_to_end(&mut contents)?;
+        let is_kml = file_name.to_lowercase().ends_with(".kml");
+        if is_kml && (kml_text.is_none() || file_name == "doc.kml") {
+            kml_text = Some(String::from_utf8_lossy(&contents).into_owned());
+        } else {
+            files.insert(file_name, contents);
+        }
+    }
+    let Some(kml_text) = kml_text else {
+        anyhow::bail!("no .kml file inside {name:?}");
+    };
+    parse_kml(name, &kml_text, &files)
+}
+
+/// `files` are the other files of a KMZ, looked up by relative path for image hrefs.
+pub fn parse_kml(
+    name: &str,
+    text: &str,
+    files: &HashMap<String, Vec<u8>>,
+) -> anyhow::Result<VectorLayer> {
+    let doc = roxmltree::Document::parse(text)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "kml" {
+        anyhow::bail!(
+            "not a KML file: root element is <{}>",
+            root.tag_name().name()
+        );
+    }
+    let styles = collect_styles(root);
+
+    let mut features = vec![];
+    let mut ground_overlays = vec![];
+    for node in root.descendants().filter(|n| n.is_element()) {
+        match node.tag_name().name() {
+            "Placemark" => features.push(parse_placemark(node, &styles)),
+            "GroundOverlay" => match parse_ground_overlay(node, files) {
+                Some(overlay) => ground_overlays.push(overlay),
+                None => dioxus_logger::tracing::warn!(
+                    "{name}: skipping GroundOverlay without image or LatLonBox"
+                ),
+            },
+            _ => {}
+        }
+    }
+    Ok(VectorLayer {
+        name: name.to_string(),
+        features,
+        gpx: None,
+        ground_overlays,
+    })
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn child_text(node: Node, name: &str) -> Option<String> {
+    child(node, name)
+        .and_then(|n| n.text())
+        .map(|t| t.trim().to_string())
+}
+
+/// KML writes colors as `aabbggrr` hex; returns `("#rrggbb", alpha)`.
+fn parse_kml_color(text: &str) -> Option<(String, f64)> {
+    let text = text.trim().trim_start_matches('#');
+    if text.len() != 8 || !text.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let alpha = u8::from_str_radix(&text[0..2], 16).ok()? as f64 / 255.0;
+    let (bb, gg, rr) = (&text[2..4], &text[4..6], &text[6..8]);
+    Some((format!("#{rr}{gg}{bb}"), alpha))
+}
+
+fn parse_style(node: Node) -> FeatureStyle {
+    let mut style = FeatureStyle::default();
+    if let Some(line) = child(node, "LineStyle") {
+        if let Some((color, alpha)) = child_text(line, "color").and_then(|c| parse_kml_color(&c)) {
+            style.stroke = color;
+            style.stroke_opacity = alpha;
+        }
+        if let Some(width) = child_text(line, "width").and_then(|w| w.parse().ok()) {
+            style.stroke_width = width;
+        }
+    }
+    if let Some(poly) = child(node, "PolyStyle") {
+        if let Some((color, alpha)) = child_text(poly, "color").and_then(|c| parse_kml_color(&c)) {
+            style.fill = color;
+            style.fill_opacity = alpha;
+        }
+        if child_text(poly, "fill").as_deref() == Some("0") {
+            style.fill_opacity = 0.0;
+        }
+        if child_text(poly, "outline").as_deref() == Some("0") {
+            style.stroke_opacity = 0.0;
+        }
+    }
+    if let Some(icon) = child(node, "IconStyle") {
+        if let Some((color, _)) = child_text(icon, "color").and_then(|c| parse_kml_color(&c)) {
+            style.marker_color = color;
+        }
+    }
+    style
+}
+
+/// Shared `<Style id>`s, and `<StyleMap id>`s resolved to their "normal" style.
+fn collect_styles(root: Node) -> HashMap<String, FeatureStyle> {
+    let mut styles = HashMap::new();
+    for node in root.descendants().filter(|n| n.has_tag_name("Style")) {
+        if let Some(id) = node.attribute("id") {
+            styles.insert(id.to_string(), parse_style(node));
+        }
+    }
+    for node in root.descendants().filter(|n| n.has_tag_name("StyleMap")) {
+        let Some(id) = node.attribute("id") else {
+            continue;
+        };
+        let normal = node
+            .children()
+            .filter(|n| n.has_tag_name("Pair"))
+            .find(|pair| child_text(*pair, "key").as_deref() == Some("normal"))
+            .and_then(|pair| child_text(pair, "styleUrl"));
+        if let Some(style) = normal.and_then(|url| styles.get(url.trim_start_matches('#'))) {
+            styles.insert(id.to_string(), style.clone());
+        }
+    }
+    styles
+}
+
+fn parse_placemark(node: Node, styles: &HashMap<String, FeatureStyle>) -> Feature {
+    let style = match child(node, "Style") {
+        Some(inline) => parse_style(inline),
+        None => child_text(node, "styleUrl")
+            .and_then(|url| styles.get(url.trim_start_matches('#')).cloned())
+            .unwrap_or_default(),
+    };
+
+    let mut properties = vec![];
+    for key in ["name", "description"] {
+        if let Some(value) = child_text(node, key) {
+            properties.push((key.to_string(), value));
+        }
+    }
+    if let Some(extended) = child(node, "ExtendedData") {
+        for data in extended.children().filter(|n| n.has_tag_name("Data")) {
+            if let (Some(key), Some(value)) = (data.attribute("name"), child_text(data, "value")) {
+                properties.push((key.to_string(), value));
+            }
+        }
+    }
+
+    properties.sort();
+
+    let mut geometries = vec![];
+    for geometry in node.children().filter(|n| n.is_element()) {
+        parse_geometry(geometry, &mut geometries);
+    }
+    Feature {
+        geometries,
+        properties,
+        style,
+    }
+}
+
+fn parse_geometry(node: Node, out: &mut Vec<Geometry>) {
+    let coordinates = || {
+        child_text(node, "coordinates")
+            .map(|c| parse_coordinates(&c))
+            .unwrap_or_default()
+    };
+    match node.tag_name().name() {
+        "Point" => out.extend(coordinates().first().copied().map(Geometry::Point)),
+        "LineString" => out.push(Geometry::LineString(coordinates())),
+        "LinearRing" => out.push(Geometry::Polygon(vec![coordinates()])),
+        "Polygon" => {
+            let ring = |boundary: Node| {
+                child(boundary, "LinearRing")
+                    .and_then(|r| child_text(r, "coordinates"))
+                    .map(|c| parse_coordinates(&c))
+            };
+            let mut rings = vec![];
+            rings.extend(child(node, "outerBoundaryIs").and_then(ring));
+            rings.extend(
+                node.children()
+                    .filter(|n| n.has_tag_name("innerBoundaryIs"))
+                    .filter_map(ring),
+            );
+            if !rings.is_empty() {
+                out.push(Geometry::Polygon(rings));
+            }
+        }
+        "MultiGeometry" => {
+            for part in node.children().filter(|n| n.is_element()) {
+                parse_geometry(part, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// `lon,lat[,alt]` tuples separated by whitespace, returned as `(lat, lon)`.
+fn parse_coordinates(text: &str) -> Vec<(f64, f64)> {
+    text.split_whitespace()
+        .filter_map(|tuple| {
+            let mut parts = tuple.split(',');
+            let lon = parts.next()?.parse::<f64>().ok()?;
+            let lat = parts.next()?.parse::<f64>().ok()?;
+            Some((lat, lon))
+        })
+        .collect()
+}
+
+fn parse_ground_overlay(node: Node, files: &HashMap<String, Vec<u8>>) -> Option<GroundOverlay> {
+    let href = child(node, "Icon").and_then(|icon| child_text(icon, "href"))?;
+    let image_src = match files.get(&href) {
+        Some(bytes) => TileImage::from_bytes(bytes.clone(), "image/png").to_data_url(),
+        None => href,
+    };
+    let bbox = child(node, "LatLonBox")?;
+    let edge = |name: &str| child_text(bbox, name).and_then(|v| v.parse::<f64>().ok());
+    Some(GroundOverlay {
+        name: child_text(node, "name").unwrap_or_default(),
+        image_src,
+        bounds: LatLonBounds {
+            north: edge("north")?,
+            south: edge("south")?,
+            west: edge("west")?,
+            east: edge("east")?,
+        },
+        rotation_deg: edge("rotation").unwrap_or(0.0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2">
+  <Document>
+    <Style id="red_line">
+      <LineStyle><color>ff0000ff</color><width>4</width></LineStyle>
+      <PolyStyle><color>7f00ff00</color></PolyStyle>
+    </Style>
+    <Style id="red_line_hover">
+      <LineStyle><color>ffffffff</color></LineStyle>
+    </Style>
+    <StyleMap id="red_line_map">
+      <Pair><key>highlight</key><styleUrl>#red_line_hover</styleUrl></Pair>
+      <Pair><key>normal</key><styleUrl>#red_line</styleUrl></Pair>
+    </StyleMap>
+    <Placemark>
+      <name>Lake</name>
+      <styleUrl>#red_line_map</styleUrl>
+      <ExtendedData><Data name="depth"><value>12</value></Data></ExtendedData>
+      <Polygon>
+        <outerBoundaryIs><LinearRing><coordinates>
+          26.0,44.0,0 26.1,44.0,0 26.1,44.1,0 26.0,44.0,0
+        </coordinates></LinearRing></outerBoundaryIs>
+        <innerBoundaryIs><LinearRing><coordinates>
+          26.02,44.02 26.04,44.02 26.04,44.04 26.02,44.02
+        </coordinates></LinearRing></innerBoundaryIs>
+      </Polygon>
+    </Placemark>
+    <Placemark>
+      <name>Hut</name>
+      <MultiGeometry>
+        <Point><coordinates>26.05,44.05,300</coordinates></Point>
+        <LineString><coordinates>26.0,44.0 26.05,44.05</coordinates></LineString>
+      </MultiGeometry>
+    </Placemark>
+    <GroundOverlay>
+      <name>Old map</name>
+      <Icon><href>files/map.png</href></Icon>
+      <LatLonBox>
+        <north>44.2</north><south>44.0</south>
+        <east>26.3</east><west>26.0</west>
+        <rotation>12.5</rotation>
+      </LatLonBox>
+    </GroundOverlay>
+  </Document>
+</kml>"##;
+
+    #[test]
+    fn kml_colors_are_aabbggrr() {
+        assert_eq!(parse_kml_color("ff0000ff"), Some(("#ff0000".into(), 1.0)));
+        assert_eq!(
+            parse_kml_color("#7f00ff00"),
+            Some(("#00ff00".into(), 127.0 / 255.0))
+        );
+        assert_eq!(parse_kml_color(" 00123456 "), Some(("#563412".into(), 0.0)));
+        assert_eq!(parse_kml_color("ff0000"), None);
+        assert_eq!(parse_kml_color("ff0000gg"), None);
+    }
+
+    #[test]
+    fn placemarks_with_style_maps_and_rings() {
+        let layer = parse_kml("lake.kml", SAMPLE, &HashMap::new()).unwrap();
+        assert_eq!(layer.features.len(), 2);
+
+        let lake = &layer.features[0];
+        // the StyleMap resolves to its "normal" style, not the highlight one
+        assert_eq!(lake.style.stroke, "#ff0000");
+        assert_eq!(lake.style.stroke_width, 4.0);
+        assert_eq!(lake.style.fill, "#00ff00");
+        assert!((lake.style.fill_opacity - 127.0 / 255.0).abs() < 1e-12);
+        assert_eq!(
+            lake.properties,
+            vec![
+                ("depth".to_string(), "12".to_string()),
+                ("name".to_string(), "Lake".to_string())
+            ]
+        );
+        let [Geometry::Polygon(rings)] = lake.geometries.as_slice() else {
+            panic!("expected one polygon, got {:?}", lake.geometries);
+        };
+        assert_eq!(rings.len(), 2);
+        assert_eq!(rings[0][1], (44.0, 26.1));
+        assert_eq!(rings[1][0], (44.02, 26.02));
+
+        let hut = &layer.features[1];
+        assert_eq!(hut.style, FeatureStyle::default());
+        assert_eq!(
+            hut.geometries,
+            vec![
+                Geometry::Point((44.05, 26.05)),
+                Geometry::LineString(vec![(44.0, 26.0), (44.05, 26.05)])
+            ]
+        );
+    }
+
+    #[test]
+    fn ground_overlay_with_rotation() {
+        let layer = parse_kml("map.kml", SAMPLE, &HashMap::new()).unwrap();
+        let [overlay] = layer.ground_overlays.as_slice() else {
+            panic!("expected one ground overlay");
+        };
+        assert_eq!(overlay.name, "Old map");
+        // not inside a KMZ: the href is used as it is
+        assert_eq!(overlay.image_src, "files/map.png");
+        assert_eq!(
+            overlay.bounds,
+            LatLonBounds {
+                north: 44.2,
+                south: 44.0,
+                west: 26.0,
+                east: 26.3,
+            }
+        );
+        assert_eq!(overlay.rotation_deg, 12.5);
+    }
+
+    #[test]
+    fn kmz_images_become_data_urls() {
+        use std::io::Write;
+
+        let png = b"\x89PNG not really".to_vec();
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("other.kml", options).unwrap();
+        zip.write_all(b"<kml/>").unwrap();
+        zip.start_file("doc.kml", options).unwrap();
+        zip.write_all(SAMPLE.as_bytes()).unwrap();
+        zip.start_file("files/map.png", options).unwrap();
+        zip.write_all(&png).unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+
+        let layer = parse_kmz("map.kmz", &bytes).unwrap();
+        assert_eq!(layer.features.len(), 2);
+        assert_eq!(
+            layer.ground_overlays[0].image_src,
+            TileImage::from_bytes(png, "image/png").to_data_url()
+        );
+        assert!(layer.ground_overlays[0]
+            .image_src
+            .starts_with("data:image/png;base64,"));
+
+        assert!(parse_kmz("bad.kmz", b"not a zip").is_err());
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        zip.start_file("readme.txt", options).unwrap();
+        let no_kml = zip.finish().unwrap().into_inner();
+        assert!(parse_kmz("empty.kmz", &no_kml).is_err());
+    }
+}
@@ -1,7 +1,22 @@
 pub mod _const;
+pub mod animation;
 pub mod comp;
+pub mod coord_format;
 pub mod data_loader;
 pub mod geometry;
+pub mod gpx;
 pub mod index_db;
 pub mod input;
+pub mod kml;
+pub mod markers;
+pub mod measure;
+#[cfg(feature = "server")]
+pub mod mbtiles;
+pub mod overlay;
+#[cfg(feature = "server")]
+pub mod pmtiles;
+#[cfg(feature = "server")]
+pub mod tile_cache;
+pub mod tile_protocol;
+pub mod tile_provider;
 pub mod url_state;
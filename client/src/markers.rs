@@ -0,0 +1,86 @@
+//! Points the user pinned on the map, kept in IndexedDB next to the tile cache.
+
+use dioxus::prelude::*;
+use dioxus_logger::tracing::error;
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::world_to_lat_lon;
+use crate::index_db::{delete_marker, read_markers, write_marker, DbReesource};
+
+/// Icons offered in the marker popup; the first one is used for new markers.
+pub const MARKER_ICONS: &[&str] = &["📍", "⭐", "🏠", "⛺", "🚩", "⚠️"];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Marker {
+    pub id: String,
+    /// WGS84 `(lat, lon)`, so markers survive changes to the world units.
+    pub lat_lon: (f64, f64),
+    pub icon: String,
+    pub label: String,
+    /// free text shown when the marker is clicked
+    pub popup: String,
+}
+
+/// Markers stored in IndexedDB, loaded once the database is open.
+pub fn use_markers() -> Signal<Vec<Marker>> {
+    let mut markers = use_signal(Vec::<Marker>::new);
+    let db_res = use_context::<DbReesource>();
+    use_effect(move || {
+        if !matches!(*db_res.read(), Some(Ok(_))) {
+            return;
+        }
+        spawn(async move {
+            match read_markers().await {
+                Ok(mut loaded) => {
+                    loaded.sort_by(|a, b| a.id.cmp(&b.id));
+                    markers.set(loaded);
+                }
+                Err(e) => error!("failed to read markers from indexed db: {:#?}", e),
+            }
+        });
+    });
+    markers
+}
+
+/// Adds a marker at the world point `pos` and stores it.
+pub fn add_marker(mut markers: Signal<Vec<Marker>>, pos: (f64, f64)) {
+    let millis = web_time::SystemTime::now()
+        .duration_since(web_time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let marker = Marker {
+        // zero padded, so that sorting by id sorts by creation time
+        id: format!("{millis:016}"),
+        lat_lon: world_to_lat_lon(pos),
+        icon: MARKER_ICONS[0].to_string(),
+        label: format!("marker {}", markers.peek().len() + 1),
+        popup: String::new(),
+    };
+    markers.write().push(marker.clone());
+    spawn(async move {
+        if let Err(e) = write_marker(&marker).await {
+            error!("failed to write marker to indexed db: {:#?}", e);
+        }
+    });
+}
+
+/// Replaces the stored marker with the same id.
+pub fn update_marker(mut markers: Signal<Vec<Marker>>, marker: Marker) {
+    if let Some(m) = markers.write().iter_mut().find(|m| m.id == marker.id) {
+        *m = marker.clone();
+    }
+    spawn(async move {
+        if let Err(e) = write_marker(&marker).await {
+            error!("failed to write marker to indexed db: {:#?}", e);
+        }
+    });
+}
+
+pub fn remove_marker(mut markers: Signal<Vec<Marker>>, id: String) {
+    markers.write().retain(|m| m.id != id);
+    spawn(async move {
+        if let Err(e) = delete_marker(id).await {
+            error!("failed to delete marker from indexed db: {:#?}", e);
+        }
+    });
+}
@@ -0,0 +1,257 @@
+//! Read and write tiles in MBTiles files (SQLite, tiles stored in TMS order).
+//!
+//! MBTiles counts `tile_row` up from the south, while the rest of the app uses
+//! XYZ rows counted down from the north (see `geometry::get_tile_positions`),
+//! so every access goes through [`xyz_to_tms_row`].
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use dioxus_logger::tracing::info;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+
+use crate::geometry::MAX_LATITUDE;
+use crate::tile_provider::TileImage;
+
+pub struct MbTiles {
+    conn: Mutex<Connection>,
+    /// content type for tiles whose magic bytes we do not recognise, from the `format` metadata.
+    fallback_content_type: String,
+}
+
+/// Flips an XYZ row into an MBTiles (TMS) row and back - the flip is its own inverse.
+pub fn xyz_to_tms_row(sq_z: i32, sq_y: i32) -> i32 {
+    (1 << sq_z) - 1 - sq_y
+}
+
+impl MbTiles {
+    pub fn open_read_only(path: &str) -> anyhow::Result<Self> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        Self::from_connection(conn)
+    }
+
+    /// Opens the file for writing, creating it and the MBTiles schema if needed.
+    pub fn open_read_write(path: &str) -> anyhow::Result<Self> {
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let conn = Connection::open(path)?;
+        let name = std::path::Path::new(path)
+            .file_stem()
+            .map_or(path.into(), |stem| stem.to_string_lossy());
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS metadata (name TEXT, value TEXT);
+             CREATE UNIQUE INDEX IF NOT EXISTS metadata_name ON metadata (name);
+             CREATE TABLE IF NOT EXISTS tiles (
+                 zoom_level INTEGER,
+                 tile_column INTEGER,
+                 tile_row INTEGER,
+                 tile_data BLOB
+             );
+             CREATE UNIQUE INDEX IF NOT EXISTS tile_index
+                 ON tiles (zoom_level, tile_column, tile_row);",
+        )?;
+        // required by MBTiles 1.3; `format` and the zoom range follow the written tiles
+        let bounds = format!("-180,{},180,{}", -MAX_LATITUDE, MAX_LATITUDE);
+        for (key, value) in [("name", name.as_ref()), ("bounds", bounds.as_str())] {
+            conn.execute(
+                "INSERT OR IGNORE INTO metadata (name, value) VALUES (?1, ?2)",
+                (key, value),
+            )?;
+        }
+        Self::from_connection(conn)
+    }
+
+    fn from_connection(conn: Connection) -> anyhow::Result<Self> {
+        let format: Option<String> = conn
+            .query_row(
+                "SELECT value FROM metadata WHERE name = 'format'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let fallback_content_type = match format.as_deref() {
+            Some("jpg") | Some("jpeg") => "image/jpeg",
+            Some("webp") => "image/webp",
+            Some("pbf") => "application/x-protobuf",
+            _ => "image/png",
+        }
+        .to_string();
+        Ok(Self {
+            conn: Mutex::new(conn),
+            fallback_content_type,
+        })
+    }
+
+    pub fn read_tile(&self, coord: (i32, i32, i32)) -> anyhow::Result<Option<TileImage>> {
+        let (sq_z, sq_x, sq_y) = coord;
+        let conn = self.conn.lock().unwrap();
+        let data: Option<Vec<u8>> = conn
+            .query_row(
+                "SELECT tile_data FROM tiles
+                 WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                (sq_z, sq_x, xyz_to_tms_row(sq_z, sq_y)),
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(data.map(|bytes| TileImage::from_bytes(bytes, &self.fallback_content_type)))
+    }
+
+    pub fn has_tile(&self, coord: (i32, i32, i32)) -> anyhow::Result<bool> {
+        let (sq_z, sq_x, sq_y) = coord;
+        let conn = self.conn.lock().unwrap();
+        let found = conn
+            .query_row(
+                "SELECT 1 FROM tiles
+                 WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                (sq_z, sq_x, xyz_to_tms_row(sq_z, sq_y)),
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    /// Writes the tile and the metadata it changes in one transaction, so one commit.
+    pub fn write_tile(&self, coord: (i32, i32, i32), img: &TileImage) -> anyhow::Result<()> {
+        let (sq_z, sq_x, sq_y) = coord;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data)
+             VALUES (?1, ?2, ?3, ?4)",
+            (sq_z, sq_x, xyz_to_tms_row(sq_z, sq_y), &img.bytes),
+        )?;
+        if let Some(format) = mbtiles_format(&img.content_type) {
+            tx.execute(
+                "INSERT OR IGNORE INTO metadata (name, value) VALUES ('format', ?1)",
+                [format],
+            )?;
+        }
+        tx.execute(
+            "INSERT INTO metadata (name, value) VALUES ('minzoom', ?1)
+             ON CONFLICT (name) DO UPDATE
+                 SET value = min(CAST(value AS INTEGER), CAST(excluded.value AS INTEGER))",
+            [sq_z],
+        )?;
+        tx.execute(
+            "INSERT INTO metadata (name, value) VALUES ('maxzoom', ?1)
+             ON CONFLICT (name) DO UPDATE
+                 SET value = max(CAST(value AS INTEGER), CAST(excluded.value AS INTEGER))",
+            [sq_z],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// The `format` metadata value for a tile content type.
+fn mbtiles_format(content_type: &str) -> Option<&'static str> {
+    match content_type {
+        "image/png" => Some("png"),
+        "image/jpeg" => Some("jpg"),
+        "image/webp" => Some("webp"),
+        "application/x-protobuf" => Some("pbf"),
+        _ => None,
+    }
+}
+
+/// Open connections by `(path, writable)`.
+type OpenFiles = Mutex<HashMap<(String, bool), Arc<MbTiles>>>;
+
+/// Keeps one open connection per file, so we do not reopen SQLite for every tile.
+fn open_file(path: &str, writable: bool) -> anyhow::Result<Arc<MbTiles>> {
+    static OPEN_FILES: OnceLock<OpenFiles> = OnceLock::new();
+    let mut open_files = OPEN_FILES.get_or_init(Default::default).lock().unwrap();
+    let key = (path.to_string(), writable);
+    if let Some(mbtiles) = open_files.get(&key) {
+        return Ok(mbtiles.clone());
+    }
+    info!("mbtiles: opening {path:?} (writable: {writable})");
+    let mbtiles = Arc::new(if writable {
+        MbTiles::open_read_write(path)?
+    } else {
+        MbTiles::open_read_only(path)?
+    });
+    open_files.insert(key, mbtiles.clone());
+    Ok(mbtiles)
+}
+
+pub async fn read_tile(path: &str, coord: (i32, i32, i32)) -> anyhow::Result<Option<TileImage>> {
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || open_file(&path, false)?.read_tile(coord)).await?
+}
+
+pub async fn has_tile(path: &str, coord: (i32, i32, i32)) -> anyhow::Result<bool> {
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || open_file(&path, true)?.has_tile(coord)).await?
+}
+
+pub async fn write_tile(path: &str, coord: (i32, i32, i32), img: TileImage) -> anyhow::Result<()> {
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || open_file(&path, true)?.write_tile(coord, &img)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tms_rows_count_from_the_south() {
+        assert_eq!(xyz_to_tms_row(0, 0), 0);
+        assert_eq!(xyz_to_tms_row(1, 0), 1);
+        assert_eq!(xyz_to_tms_row(1, 1), 0);
+        assert_eq!(xyz_to_tms_row(3, 2), 5);
+        assert_eq!(xyz_to_tms_row(3, xyz_to_tms_row(3, 2)), 2);
+    }
+
+    #[test]
+    fn written_tiles_read_back_with_metadata() {
+        let path =
+            std::env::temp_dir().join(format!("mbtiles_test_{}.mbtiles", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+        let png = TileImage::from_bytes(b"\x89PNG fake".to_vec(), "image/png");
+        {
+            let mbtiles = MbTiles::open_read_write(path).unwrap();
+            mbtiles.write_tile((3, 1, 2), &png).unwrap();
+            mbtiles.write_tile((5, 0, 0), &png).unwrap();
+            mbtiles.write_tile((4, 0, 0), &png).unwrap();
+        }
+
+        let mbtiles = MbTiles::open_read_only(path).unwrap();
+        assert_eq!(
+            mbtiles.read_tile((3, 1, 2)).unwrap().unwrap().bytes,
+            png.bytes
+        );
+        assert!(mbtiles.read_tile((3, 1, 5)).unwrap().is_none());
+        assert!(mbtiles.has_tile((4, 0, 0)).unwrap());
+        assert!(!mbtiles.has_tile((4, 0, 1)).unwrap());
+        let conn = mbtiles.conn.lock().unwrap();
+        // stored under the TMS row
+        let row: i32 = conn
+            .query_row(
+                "SELECT tile_row FROM tiles WHERE zoom_level = 3",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(row, 5);
+        let meta = |name: &str| -> String {
+            conn.query_row(
+                "SELECT value FROM metadata WHERE name = ?1",
+                [name],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(meta("name"), format!("mbtiles_test_{}", std::process::id()));
+        assert_eq!(meta("format"), "png");
+        assert_eq!(meta("minzoom"), "3");
+        assert_eq!(meta("maxzoom"), "5");
+        assert!(meta("bounds").starts_with("-180,-85.05"));
+        drop(conn);
+        std::fs::remove_file(path).unwrap();
+    }
+}
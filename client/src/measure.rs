@@ -0,0 +1,248 @@
+//! Distance and area measuring on the WGS84 ellipsoid, for the measure tool.
+
+use dioxus::prelude::*;
+
+use crate::geometry::{distance_m, world_to_lat_lon, EARTH_RADIUS_M};
+
+/// WGS84 flattening.
+const FLATTENING: f64 = 1.0 / 298.257_223_563;
+/// Vincenty gives up after this many iterations; it only happens for nearly antipodal points.
+const VINCENTY_MAX_ITERATIONS: usize = 200;
+
+/// Points clicked in measure mode.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Measurement {
+    /// WGS84 `(lat, lon)`, in click order
+    pub points: Vec<(f64, f64)>,
+    /// measure a polygon: adds the closing segment and the enclosed area
+    pub closed: bool,
+}
+
+impl Measurement {
+    /// One length per segment, the closing one last when `closed`.
+    pub fn segment_lengths_m(&self) -> Vec<f64> {
+        let mut lengths = self
+            .points
+            .windows(2)
+            .map(|w| geodesic_distance_m(w[0], w[1]))
+            .collect::<Vec<_>>();
+        if let (true, Some(first), Some(last)) = (
+            self.closed && self.points.len() >= 3,
+            self.points.first(),
+            self.points.last(),
+        ) {
+            lengths.push(geodesic_distance_m(*last, *first));
+        }
+        lengths
+    }
+
+    pub fn total_length_m(&self) -> f64 {
+        self.segment_lengths_m().iter().sum()
+    }
+
+    /// `None` unless `closed` with at least three points.
+    pub fn area_m2(&self) -> Option<f64> {
+        (self.closed && self.points.len() >= 3).then(|| polygon_area_m2(&self.points))
+    }
+}
+
+/// Adds the world point `pos` to the measurement, if measure mode is on.
+pub fn add_measure_point(mut measurement: Signal<Option<Measurement>>, pos: (f64, f64)) {
+    if measurement.peek().is_none() {
+        return;
+    }
+    if let Some(m) = measurement.write().as_mut() {
+        m.points.push(world_to_lat_lon(pos));
+    }
+}
+
+/// Distance in meters along the ellipsoid (Vincenty's inverse formula), accurate to
+/// well under a millimeter. Nearly antipodal points, where the iteration does not
+/// converge, fall back to the great-circle distance.
+pub fn geodesic_distance_m(a: (f64, f64), b: (f64, f64)) -> f64 {
+    vincenty_distance_m(a, b).unwrap_or_else(|| distance_m(a, b))
+}
+
+fn vincenty_distance_m(a: (f64, f64), b: (f64, f64)) -> Option<f64> {
+    let f = FLATTENING;
+    let semi_major = EARTH_RADIUS_M;
+    let semi_minor = (1.0 - f) * semi_major;
+
+    let l = (b.1 - a.1).to_radians();
+    let u1 = ((1.0 - f) * a.0.to_radians().tan()).atan();
+    let u2 = ((1.0 - f) * b.0.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..VINCENTY_MAX_ITERATIONS {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = f64::hypot(
+            cos_u2 * sin_lambda,
+            cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda,
+        );
+        if sin_sigma == 0.0 {
+            // same point
+            return Some(0.0);
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+        // zero on the equator
+        let cos_2sigma_m = if cos_sq_alpha != 0.0 {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha
+        } else {
+            0.0
+        };
+        let c = f / 16.0 * cos_sq_alpha * (4.0 + f * (4.0 - 3.0 * cos_sq_alpha));
+        let lambda_prev = lambda;
+        lambda = l
+            + (1.0 - c)
+                * f
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))));
+        if (lambda - lambda_prev).abs() < 1e-12 {
+            let u_sq =
+                cos_sq_alpha * (semi_major.powi(2) - semi_minor.powi(2)) / semi_minor.powi(2);
+            let big_a =
+                1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+            let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+            let delta_sigma = big_b
+                * sin_sigma
+                * (cos_2sigma_m
+                    + big_b / 4.0
+                        * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))
+                            - big_b / 6.0
+                                * cos_2sigma_m
+                                * (-3.0 + 4.0 * sin_sigma.powi(2))
+                                * (-3.0 + 4.0 * cos_2sigma_m.powi(2))));
+            return Some(semi_minor * big_a * (sigma - delta_sigma));
+        }
+    }
+    None
+}
+
+/// Area in square meters of the polygon through `points` (WGS84 `(lat, lon)`, implicitly
+/// closed). Latitudes go to the authalic sphere, which has the same surface area as the
+/// ellipsoid, and the edges are taken as great circles there.
+pub fn polygon_area_m2(points: &[(f64, f64)]) -> f64 {
+    let f = FLATTENING;
+    let e_sq = f * (2.0 - f);
+    let e = e_sq.sqrt();
+    // Snyder, "Map Projections: A Working Manual", eq. 3-12
+    let q = |lat: f64| {
+        let sin_lat = lat.sin();
+        (1.0 - e_sq)
+            * (sin_lat / (1.0 - e_sq * sin_lat * sin_lat)
+                - 1.0 / (2.0 * e) * ((1.0 - e * sin_lat) / (1.0 + e * sin_lat)).ln())
+    };
+    let q_pole = q(std::f64::consts::FRAC_PI_2);
+    let authalic_radius = EARTH_RADIUS_M * (q_pole / 2.0).sqrt();
+    let authalic_lat = |lat: f64| (q(lat.to_radians()) / q_pole).clamp(-1.0, 1.0).asin();
+
+    // sum of the signed spherical excesses of the trapezoids between each edge and the equator
+    let mut excess = 0.0;
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        let (beta1, beta2) = (authalic_lat(a.0), authalic_lat(b.0));
+        let d_lon = (b.1 - a.1).to_radians();
+        // the short way around, for edges that cross the antimeridian
+        let d_lon = (d_lon + std::f64::consts::PI).rem_euclid(2.0 * std::f64::consts::PI)
+            - std::f64::consts::PI;
+        let (t1, t2) = ((beta1 / 2.0).tan(), (beta2 / 2.0).tan());
+        excess += 2.0 * f64::atan2((d_lon / 2.0).tan() * (t1 + t2), 1.0 + t1 * t2);
+    }
+    excess.abs() * authalic_radius.powi(2)
+}
+
+/// `"12.3 m"`, `"4.567 km"`.
+pub fn format_length(meters: f64) -> String {
+    if meters < 1000.0 {
+        format!("{meters:.1} m")
+    } else {
+        format!("{:.3} km", meters / 1000.0)
+    }
+}
+
+/// Square meters, hectares or square kilometers, whichever reads best.
+pub fn format_area(square_meters: f64) -> String {
+    if square_meters < 10_000.0 {
+        format!("{square_meters:.0} m²")
+    } else if square_meters < 1_000_000.0 {
+        format!("{:.2} ha", square_meters / 10_000.0)
+    } else {
+        format!("{:.3} km²", square_meters / 1_000_000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dms(degrees: f64, minutes: f64, seconds: f64) -> f64 {
+        degrees.signum() * (degrees.abs() + minutes / 60.0 + seconds / 3600.0)
+    }
+
+    #[test]
+    fn flinders_peak_to_buninyong() {
+        // the worked example in Vincenty's 1975 paper
+        let flinders_peak = (dms(-37.0, 57.0, 3.72030), dms(144.0, 25.0, 29.52440));
+        let buninyong = (dms(-37.0, 39.0, 10.15610), dms(143.0, 55.0, 35.38390));
+        let d = geodesic_distance_m(flinders_peak, buninyong);
+        assert!((d - 54_972.271).abs() < 1e-3, "{d}");
+        assert_eq!(geodesic_distance_m(buninyong, buninyong), 0.0);
+    }
+
+    #[test]
+    fn nearly_antipodal_points_fall_back_to_great_circle() {
+        let (a, b) = ((0.0, 0.0), (0.5, 179.7));
+        assert_eq!(vincenty_distance_m(a, b), None);
+        let d = geodesic_distance_m(a, b);
+        assert_eq!(d, distance_m(a, b));
+        assert!((19_900_000.0..20_040_000.0).contains(&d), "{d}");
+    }
+
+    #[test]
+    fn closed_measurement_adds_the_closing_segment() {
+        let mut m = Measurement {
+            points: vec![(0.0, 0.0), (0.0, 1.0), (1.0, 1.0)],
+            closed: false,
+        };
+        assert_eq!(m.segment_lengths_m().len(), 2);
+        assert_eq!(m.area_m2(), None);
+        m.closed = true;
+        assert_eq!(m.segment_lengths_m().len(), 3);
+        assert!(m.area_m2().is_some());
+    }
+
+    #[test]
+    fn equator_cell_area() {
+        let cell = [(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0)];
+        let km2 = polygon_area_m2(&cell) / 1e6;
+        assert!((km2 - 12_308.0).abs() < 2.0, "{km2}");
+        // winding does not matter
+        let mut reversed = cell;
+        reversed.reverse();
+        assert!((polygon_area_m2(&reversed) / 1e6 - km2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn polygon_across_the_antimeridian() {
+        let cell = [(0.0, 179.5), (0.0, -179.5), (1.0, -179.5), (1.0, 179.5)];
+        let same = [(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0)];
+        let (across, reference) = (polygon_area_m2(&cell), polygon_area_m2(&same));
+        assert!((across - reference).abs() < 1.0, "{across} != {reference}");
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format_length(12.34), "12.3 m");
+        assert_eq!(format_length(4567.0), "4.567 km");
+        assert_eq!(format_area(500.0), "500 m²");
+        assert_eq!(format_area(25_000.0), "2.50 ha");
+        assert_eq!(format_area(12_308_000_000.0), "12308.000 km²");
+    }
+}
@@ -0,0 +1,393 @@
+//! Vector overlays loaded from files: parsed once into lat/lon geometry, then
+//! projected to the screen on every frame by `comp::MapsVectorOverlay`.
+
+use serde_json::Value;
+
+/// One loaded file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VectorLayer {
+    /// file name it was loaded from
+    pub name: String,
+    pub features: Vec<Feature>,
+    /// the parsed file, when it was a GPX, so that export keeps elevation and times
+    pub gpx: Option<crate::gpx::Gpx>,
+    /// KML `GroundOverlay` images, drawn under the vector features
+    pub ground_overlays: Vec<GroundOverlay>,
+}
+
+/// An image stretched between lat/lon bounds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroundOverlay {
+    pub name: String,
+    /// a remote URL, or a data URL for images packed inside a KMZ
+    pub image_src: String,
+    pub bounds: crate::geometry::LatLonBounds,
+    /// counter-clockwise, around the center of the bounds
+    pub rotation_deg: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Feature {
+    /// multi-geometries and geometry collections are flattened into this list
+    pub geometries: Vec<Geometry>,
+    /// `(key, value)` sorted by key, values written as text
+    pub properties: Vec<(String, String)>,
+    pub style: FeatureStyle,
+}
+
+/// Coordinates are WGS84 `(lat, lon)`, like everywhere else in the app.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Geometry {
+    Point((f64, f64)),
+    LineString(Vec<(f64, f64)>),
+    /// outer ring first, then holes
+    Polygon(Vec<Vec<(f64, f64)>>),
+}
+
+/// The drawing options of the `simplestyle-spec` properties:
+/// `stroke`, `stroke-width`, `stroke-opacity`, `fill`, `fill-opacity` and `marker-color`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeatureStyle {
+    pub stroke: String,
+    pub stroke_width: f64,
+    pub stroke_opacity: f64,
+    pub fill: String,
+    pub fill_opacity: f64,
+    pub marker_color: String,
+}
+
+impl Default for FeatureStyle {
+    fn default() -> Self {
+        Self {
+            stroke: "#555555".to_string(),
+            stroke_width: 2.0,
+            stroke_opacity: 1.0,
+            fill: "#555555".to_string(),
+            fill_opacity: 0.6,
+            marker_color: "#7e7e7e".to_string(),
+        }
+    }
+}
+
+impl FeatureStyle {
+    fn from_properties(properties: Option<&serde_json::Map<String, Value>>) -> Self {
+        let mut style = Self::default();
+        let Some(properties) = properties else {
+            return style;
+        };
+        let text = |key: &str| {
+            properties
+                .get(key)
+                .and_then(Value::as_str)
+                .map(String::from)
+        };
+        let number = |key: &str| properties.get(key).and_then(Value::as_f64);
+        if let Some(stroke) = text("stroke") {
+            style.stroke = stroke;
+        }
+        if let Some(width) = number("stroke-width") {
+            style.stroke_width = width.max(0.0);
+        }
+        if let Some(opacity) = number("stroke-opacity") {
+            style.stroke_opacity = opacity.clamp(0.0, 1.0);
+        }
+        if let Some(fill) = text("fill") {
+            style.fill = fill;
+        }
+        if let Some(opacity) = number("fill-opacity") {
+            style.fill_opacity = opacity.clamp(0.0, 1.0);
+        }
+        if let Some(color) = text("marker-color") {
+            style.marker_color = color;
+        }
+        style
+    }
+}
+
+/// Picks the parser from the file extension.
+pub fn parse_vector_file(name: &str, bytes: &[u8]) -> anyhow::Result<VectorLayer> {
+    let extension = name.rsplit('.').next().unwrap_or_default().to_lowercase();
+    match extension.as_str() {
+        "geojson" | "json" => parse_geojson(name, &String::from_utf8_lossy(bytes)),
+        "gpx" => Ok(crate::gpx::parse_gpx(&String::from_utf8_lossy(bytes))?.to_vector_layer(name)),
+        "kml" => crate::kml::parse_kml(name, &String::from_utf8_lossy(bytes), &Default::default()),
+        "kmz" => crate::kml::parse_kmz(name, bytes),
+        _ => anyhow::bail!("unsupported file type {extension:?}"),
+    }
+}
+
+/// Reads a FeatureCollection, a single Feature or a bare geometry.
+pub fn parse_geojson(name: &str, text: &str) -> anyhow::Result<VectorLayer> {
+    let root: Value = serde_json::from_str(text)?;
+    let features = match root.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => root
+            .get("features")
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow::anyhow!("FeatureCollection without features"))?
+            .iter()
+            .map(parse_geojson_feature)
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        Some("Feature") => vec![parse_geojson_feature(&root)?],
+        Some(_) => vec![Feature {
+            geometries: parse_geojson_geometry(&root)?,
+            properties: vec![],
+            style: FeatureStyle::default(),
+        }],
+        None => anyhow::bail!("not GeoJSON: no \"type\""),
+    };
+    Ok(VectorLayer {
+        name: name.to_string(),
+        features,
+        gpx: None,
+        ground_overlays: vec![],
+    })
+}
+
+fn parse_geojson_feature(feature: &Value) -> anyhow::Result<Feature> {
+    let properties = feature.get("properties").and_then(Value::as_object);
+    let geometries = match feature.get("geometry") {
+        // features without geometry are allowed, there is just nothing to draw
+        None | Some(Value::Null) => vec![],
+        Some(geometry) => parse_geojson_geometry(geometry)?,
+    };
+    Ok(Feature {
+        geometries,
+        properties: properties
+            .map(|p| {
+                p.iter()
+                    .map(|(k, v)| {
+                        let v = match v {
+                            Value::String(s) => s.clone(),
+                            v => v.to_string(),
+                        };
+                        (k.clone(), v)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        style: FeatureStyle::from_properties(properties),
+    })
+}
+
+fn parse_geojson_geometry(geometry: &Value) -> anyhow::Result<Vec<Geometry>> {
+    let kind = geometry
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow::anyhow!("geometry without type"))?;
+    if kind == "GeometryCollection" {
+        let mut out = vec![];
+        for g in geometry
+            .get("geometries")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            out.append(&mut parse_geojson_geometry(g)?);
+        }
+        return Ok(out);
+    }
+    let coords = geometry
+        .get("coordinates")
+        .ok_or_else(|| anyhow::anyhow!("{kind} without coordinates"))?;
+    Ok(match kind {
+        "Point" => vec![Geometry::Point(position(coords)?)],
+        "MultiPoint" => positions(coords)?
+            .into_iter()
+            .map(Geometry::Point)
+            .collect(),
+        "LineString" => vec![Geometry::LineString(positions(coords)?)],
+        "MultiLineString" => array(coords)?
+            .iter()
+            .map(|l| Ok(Geometry::LineString(positions(l)?)))
+            .collect::<anyhow::Result<_>>()?,
+        "Polygon" => vec![Geometry::Polygon(rings(coords)?)],
+        "MultiPolygon" => array(coords)?
+            .iter()
+            .map(|p| Ok(Geometry::Polygon(rings(p)?)))
+            .collect::<anyhow::Result<_>>()?,
+        kind => anyhow::bail!("unknown geometry type {kind:?}"),
+    })
+}
+
+fn array(value: &Value) -> anyhow::Result<&Vec<Value>> {
+    value
+        .as_array()
+        .ok_or_else(|| anyhow::anyhow!("expected an array, got {value}"))
+}
+
+/// GeoJSON positions are `[lon, lat, (alt)]`.
+fn position(value: &Value) -> anyhow::Result<(f64, f64)> {
+    let p = array(value)?;
+    match (
+        p.first().and_then(Value::as_f64),
+        p.get(1).and_then(Value::as_f64),
+    ) {
+        (Some(lon), Some(lat)) => Ok((lat, lon)),
+        _ => anyhow::bail!("bad position {value}"),
+    }
+}
+
+fn positions(value: &Value) -> anyhow::Result<Vec<(f64, f64)>> {
+    array(value)?.iter().map(position).collect()
+}
+
+fn rings(value: &Value) -> anyhow::Result<Vec<Vec<(f64, f64)>>> {
+    array(value)?.iter().map(positions).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feature_collection_and_bare_geometry() {
+        let layer = parse_geojson(
+            "roads.geojson",
+            r#"{"type": "FeatureCollection", "features": [
+                {"type": "Feature", "properties": {"name": "A1", "lanes": 4},
+                 "geometry": {"type": "LineString", "coordinates": [[26.1, 44.4], [26.2, 44.5]]}},
+                {"type": "Feature", "properties": null,
+                 "geometry": {"type": "Point", "coordinates": [26.1, 44.4, 80.0]}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(layer.name, "roads.geojson");
+        assert_eq!(layer.features.len(), 2);
+        assert_eq!(
+            layer.features[0].geometries,
+            vec![Geometry::LineString(vec![(44.4, 26.1), (44.5, 26.2)])]
+        );
+        assert_eq!(
+            layer.features[0].properties,
+            vec![
+                ("lanes".to_string(), "4".to_string()),
+                ("name".to_string(), "A1".to_string())
+            ]
+        );
+        // [lon, lat, alt] comes out as (lat, lon)
+        assert_eq!(
+            layer.features[1].geometries,
+            vec![Geometry::Point((44.4, 26.1))]
+        );
+        assert!(layer.features[1].properties.is_empty());
+
+        let bare = parse_geojson(
+            "p.json",
+            r#"{"type": "Point", "coordinates": [-73.9, 40.7]}"#,
+        )
+        .unwrap();
+        assert_eq!(bare.features.len(), 1);
+        assert_eq!(
+            bare.features[0].geometries,
+            vec![Geometry::Point((40.7, -73.9))]
+        );
+        assert_eq!(bare.features[0].style, FeatureStyle::default());
+
+        let single = parse_geojson(
+            "f.geojson",
+            r#"{"type": "Feature", "geometry": {"type": "Point", "coordinates": [1, 2]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            single.features[0].geometries,
+            vec![Geometry::Point((2.0, 1.0))]
+        );
+    }
+
+    #[test]
+    fn multi_geometries_are_flattened() {
+        let square = |x: f64| vec![(0.0, x), (0.0, x + 1.0), (1.0, x + 1.0), (1.0, x), (0.0, x)];
+        let layer = parse_geojson(
+            "m.geojson",
+            r#"{"type": "GeometryCollection", "geometries": [
+                {"type": "MultiPolygon", "coordinates": [
+                    [[[0, 0], [1, 0], [1, 1], [0, 1], [0, 0]],
+                     [[0.2, 0.2], [0.4, 0.2], [0.4, 0.4], [0.2, 0.2]]],
+                    [[[5, 0], [6, 0], [6, 1], [5, 1], [5, 0]]]
+                ]},
+                {"type": "MultiPoint", "coordinates": [[1, 2], [3, 4]]},
+                {"type": "GeometryCollection", "geometries": [
+                    {"type": "MultiLineString", "coordinates": [[[0, 0], [1, 1]], [[2, 2], [3, 3]]]}
+                ]}
+            ]}"#,
+        )
+        .unwrap();
+        let geometries = &layer.features[0].geometries;
+        assert_eq!(geometries.len(), 6);
+        let Geometry::Polygon(rings) = &geometries[0] else {
+            panic!("expected a polygon, got {:?}", geometries[0]);
+        };
+        assert_eq!(rings.len(), 2);
+        assert_eq!(rings[0], square(0.0));
+        assert_eq!(geometries[1], Geometry::Polygon(vec![square(5.0)]));
+        assert_eq!(geometries[2], Geometry::Point((2.0, 1.0)));
+        assert_eq!(geometries[3], Geometry::Point((4.0, 3.0)));
+        assert_eq!(
+            geometries[5],
+            Geometry::LineString(vec![(2.0, 2.0), (3.0, 3.0)])
+        );
+    }
+
+    #[test]
+    fn null_geometry_has_nothing_to_draw() {
+        let layer = parse_geojson(
+            "n.geojson",
+            r#"{"type": "FeatureCollection", "features": [
+                {"type": "Feature", "properties": {"note": "no place"}, "geometry": null},
+                {"type": "Feature", "properties": {}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(layer.features.len(), 2);
+        assert!(layer.features.iter().all(|f| f.geometries.is_empty()));
+        assert_eq!(
+            layer.features[0].properties,
+            vec![("note".to_string(), "no place".to_string())]
+        );
+    }
+
+    #[test]
+    fn simplestyle_properties() {
+        let layer = parse_geojson(
+            "s.geojson",
+            r##"{"type": "FeatureCollection", "features": [
+                {"type": "Feature", "geometry": null, "properties": {
+                    "stroke": "#ff0000", "stroke-width": -3, "stroke-opacity": 0.5,
+                    "fill": "#00ff00", "fill-opacity": 7, "marker-color": "#0000ff"}},
+                {"type": "Feature", "geometry": null, "properties": {
+                    "stroke-width": "wide", "fill-opacity": -0.5}}
+            ]}"##,
+        )
+        .unwrap();
+        assert_eq!(
+            layer.features[0].style,
+            FeatureStyle {
+                stroke: "#ff0000".into(),
+                stroke_width: 0.0,
+                stroke_opacity: 0.5,
+                fill: "#00ff00".into(),
+                fill_opacity: 1.0,
+                marker_color: "#0000ff".into(),
+            }
+        );
+        // text where a number belongs keeps the default
+        let style = &layer.features[1].style;
+        assert_eq!(style.stroke_width, FeatureStyle::default().stroke_width);
+        assert_eq!(style.fill_opacity, 0.0);
+    }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        let parse = |text: &str| parse_geojson("bad.geojson", text);
+        assert!(parse(r#"{"type": "Point", "coordinates": [1]}"#).is_err());
+        assert!(parse(r#"{"type": "Point", "coordinates": ["1", "2"]}"#).is_err());
+        assert!(parse(r#"{"type": "LineString", "coordinates": [[1, 2], 3]}"#).is_err());
+        assert!(parse(r#"{"type": "Polygon", "coordinates": [[1, 2]]}"#).is_err());
+        assert!(parse(r#"{"type": "Point"}"#).is_err());
+        assert!(parse(r#"{"type": "Circle", "coordinates": [1, 2]}"#).is_err());
+        assert!(parse(r#"{"type": "FeatureCollection"}"#).is_err());
+        assert!(parse(r#"{"coordinates": [1, 2]}"#).is_err());
+        assert!(parse("not json").is_err());
+        assert!(parse_vector_file("notes.txt", b"{}").is_err());
+    }
+}
@@ -0,0 +1,549 @@
+//! Read tiles from a local PMTiles v3 archive with range reads.
+//!
+//! Spec: <https://github.com/protomaps/PMTiles/blob/main/spec/v3/spec.md>.
+//! Tiles are addressed by a Hilbert curve tile id; the root directory is parsed once
+//! on open, leaf directories are read and cached on first use.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use dioxus_logger::tracing::info;
+
+use crate::tile_provider::TileImage;
+
+const HEADER_LEN: usize = 127;
+/// the spec allows at most root + 3 levels of leaf directories
+const MAX_DIR_DEPTH: usize = 4;
+/// tile ids of deeper levels do not fit in a u64
+const MAX_TILE_ID_ZOOM: i32 = 26;
+/// directories are small; a longer one means a corrupt archive, not a reason to allocate
+const MAX_DIR_BYTES: u64 = 16 * 1024 * 1024;
+/// no real tile comes near this
+const MAX_TILE_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Compression {
+    Unknown,
+    None,
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl Compression {
+    fn from_byte(b: u8) -> Self {
+        match b {
+            1 => Self::None,
+            2 => Self::Gzip,
+            3 => Self::Brotli,
+            4 => Self::Zstd,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Header {
+    root_dir_offset: u64,
+    root_dir_length: u64,
+    leaf_dirs_offset: u64,
+    tile_data_offset: u64,
+    internal_compression: Compression,
+    tile_compression: Compression,
+    tile_type: u8,
+    min_zoom: u8,
+    max_zoom: u8,
+}
+
+impl Header {
+    fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[0..7] != b"PMTiles" {
+            anyhow::bail!("not a PMTiles archive");
+        }
+        if bytes[7] != 3 {
+            anyhow::bail!("unsupported PMTiles version {}", bytes[7]);
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        Ok(Self {
+            root_dir_offset: u64_at(8),
+            root_dir_length: u64_at(16),
+            leaf_dirs_offset: u64_at(40),
+            tile_data_offset: u64_at(56),
+            internal_compression: Compression::from_byte(bytes[97]),
+            tile_compression: Compression::from_byte(bytes[98]),
+            tile_type: bytes[99],
+            min_zoom: bytes[100],
+            max_zoom: bytes[101],
+        })
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self.tile_type {
+            1 => "application/x-protobuf",
+            3 => "image/jpeg",
+            4 => "image/webp",
+            5 => "image/avif",
+            _ => "image/png",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct DirEntry {
+    tile_id: u64,
+    offset: u64,
+    length: u32,
+    /// 0 means the entry points at a leaf directory, not at tile data.
+    run_length: u32,
+}
+
+/// Maps `(z, x, y)` to its position on the PMTiles Hilbert curve, counting all
+/// tiles of lower zoom levels first.
+pub fn zxy_to_tile_id(sq_z: i32, sq_x: i32, sq_y: i32) -> anyhow::Result<u64> {
+    if !(0..=MAX_TILE_ID_ZOOM).contains(&sq_z) {
+        anyhow::bail!("PMTiles tile ids only go up to zoom {MAX_TILE_ID_ZOOM}, not {sq_z}");
+    }
+    let count = 1 << sq_z;
+    if !(0..count).contains(&sq_x) || !(0..count).contains(&sq_y) {
+        anyhow::bail!("tile ({sq_z}, {sq_x}, {sq_y}) is outside the world");
+    }
+    let z = sq_z as u32;
+    let base = ((1u64 << (2 * z)) - 1) / 3;
+    let (mut x, mut y) = (sq_x as u64, sq_y as u64);
+    let mut d = 0u64;
+    let mut s = 1u64 << z >> 1;
+    while s > 0 {
+        let rx = u64::from(x & s > 0);
+        let ry = u64::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s >>= 1;
+    }
+    Ok(base + d)
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> anyhow::Result<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let Some(b) = bytes.get(*pos) else {
+            anyhow::bail!("truncated varint in PMTiles directory");
+        };
+        *pos += 1;
+        value |= u64::from(b & 0x7f) << shift;
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+        if shift >= 64 {
+            anyhow::bail!("varint too long in PMTiles directory");
+        }
+    }
+}
+
+fn parse_directory(bytes: &[u8]) -> anyhow::Result<Vec<DirEntry>> {
+    let mut pos = 0;
+    let count = read_varint(bytes, &mut pos)? as usize;
+    // every entry takes at least four bytes
+    if count > bytes.len() / 4 {
+        anyhow::bail!(
+            "PMTiles directory claims {count} entries in {} bytes",
+            bytes.len()
+        );
+    }
+    let mut entries = vec![
+        DirEntry {
+            tile_id: 0,
+            offset: 0,
+            length: 0,
+            run_length: 0,
+        };
+        count
+    ];
+    let mut last_id = 0;
+    for entry in entries.iter_mut() {
+        last_id = read_varint(bytes, &mut pos)?
+            .checked_add(last_id)
+            .ok_or_else(|| anyhow::anyhow!("bad pmtiles directory tile id"))?;
+        entry.tile_id = last_id;
+    }
+    for entry in entries.iter_mut() {
+        entry.run_length = read_varint(bytes, &mut pos)? as u32;
+    }
+    for entry in entries.iter_mut() {
+        entry.length = read_varint(bytes, &mut pos)? as u32;
+    }
+    for i in 0..count {
+        let value = read_varint(bytes, &mut pos)?;
+        entries[i].offset = if value == 0 && i > 0 {
+            // tile data directly follows the previous entry
+            entries[i - 1].offset + entries[i - 1].length as u64
+        } else {
+            value
+                .checked_sub(1)
+                .ok_or_else(|| anyhow::anyhow!("bad pmtiles directory offset"))?
+        };
+    }
+    Ok(entries)
+}
+
+/// Entry with the largest tile id that is `<= tile_id`, if it covers `tile_id`.
+fn find_entry(entries: &[DirEntry], tile_id: u64) -> Option<DirEntry> {
+    let idx = entries.partition_point(|e| e.tile_id <= tile_id);
+    let entry = *entries.get(idx.checked_sub(1)?)?;
+    if entry.run_length == 0 || tile_id < entry.tile_id + entry.run_length as u64 {
+        Some(entry)
+    } else {
+        None
+    }
+}
+
+fn decompress(bytes: Vec<u8>, compression: Compression) -> anyhow::Result<Vec<u8>> {
+    match compression {
+        Compression::None | Compression::Unknown => Ok(bytes),
+        Compression::Gzip => {
+            let mut out = vec![];
+            flate2::read::GzDecoder::new(bytes.as_slice()).read_to_end(&mut out)?;
+            Ok(out)
+        }
+        c => anyhow::bail!("unsupported PMTiles compression {c:?}"),
+    }
+}
+
+pub struct PmTiles {
+    file: Mutex<File>,
+    header: Header,
+    root_dir: Vec<DirEntry>,
+    leaf_dirs: Mutex<HashMap<u64, Arc<Vec<DirEntry>>>>,
+}
+
+impl PmTiles {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut header_bytes = [0u8; HEADER_LEN];
+        file.read_exact(&mut header_bytes)?;
+        let header = Header::parse(&header_bytes)?;
+        let root = read_range(
+            &mut file,
+            header.root_dir_offset,
+            header.root_dir_length,
+            MAX_DIR_BYTES,
+        )?;
+        let root_dir = parse_directory(&decompress(root, header.internal_compression)?)?;
+        info!(
+            "pmtiles: opened {path:?}: zoom {}..={}, {} root entries",
+            header.min_zoom,
+            header.max_zoom,
+            root_dir.len()
+        );
+        Ok(Self {
+            file: Mutex::new(file),
+            header,
+            root_dir,
+            leaf_dirs: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn leaf_dir(&self, offset: u64, length: u64) -> anyhow::Result<Arc<Vec<DirEntry>>> {
+        if let Some(dir) = self.leaf_dirs.lock().unwrap().get(&offset) {
+            return Ok(dir.clone());
+        }
+        let start = self
+            .header
+            .leaf_dirs_offset
+            .checked_add(offset)
+            .ok_or_else(|| anyhow::anyhow!("PMTiles leaf directory offset overflows"))?;
+        let bytes = read_range(&mut self.file.lock().unwrap(), start, length, MAX_DIR_BYTES)?;
+        let dir = Arc::new(parse_directory(&decompress(
+            bytes,
+            self.header.internal_compression,
+        )?)?);
+        self.leaf_dirs.lock().unwrap().insert(offset, dir.clone());
+        Ok(dir)
+    }
+
+    pub fn read_tile(&self, coord: (i32, i32, i32)) -> anyhow::Result<Option<TileImage>> {
+        let (sq_z, sq_x, sq_y) = coord;
+        if sq_z < self.header.min_zoom as i32 || sq_z > self.header.max_zoom as i32 {
+            return Ok(None);
+        }
+        let tile_id = zxy_to_tile_id(sq_z, sq_x, sq_y)?;
+        let mut dir = Arc::new(self.root_dir.clone());
+        for _ in 0..MAX_DIR_DEPTH {
+            let Some(entry) = find_entry(&dir, tile_id) else {
+                return Ok(None);
+            };
+            if entry.run_length == 0 {
+                dir = self.leaf_dir(entry.offset, entry.length as u64)?;
+                continue;
+            }
+            let start = self
+                .header
+                .tile_data_offset
+                .checked_add(entry.offset)
+                .ok_or_else(|| anyhow::anyhow!("PMTiles tile offset overflows"))?;
+            let bytes = read_range(
+                &mut self.file.lock().unwrap(),
+                start,
+                entry.length as u64,
+                MAX_TILE_BYTES,
+            )?;
+            let bytes = decompress(bytes, self.header.tile_compression)?;
+            return Ok(Some(TileImage::from_bytes(
+                bytes,
+                self.header.content_type(),
+            )));
+        }
+        anyhow::bail!("PMTiles directories nested deeper than {MAX_DIR_DEPTH} levels")
+    }
+}
+
+/// Reads `length` bytes at `offset`, after checking them against `max_length` and the
+/// file size: both come from the archive, and must not decide how much we allocate.
+fn read_range(
+    file: &mut File,
+    offset: u64,
+    length: u64,
+    max_length: u64,
+) -> anyhow::Result<Vec<u8>> {
+    if length > max_length {
+        anyhow::bail!("PMTiles range of {length} bytes is over the {max_length} byte limit");
+    }
+    let file_len = file.metadata()?.len();
+    match offset.checked_add(length) {
+        Some(end) if end <= file_len => {}
+        _ => anyhow::bail!(
+            "PMTiles range of {length} bytes at {offset} is past the end of the file ({file_len} bytes)"
+        ),
+    }
+    let mut buf = vec![0u8; length as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn open_file(path: &str) -> anyhow::Result<Arc<PmTiles>> {
+    static OPEN_FILES: OnceLock<Mutex<HashMap<String, Arc<PmTiles>>>> = OnceLock::new();
+    let mut open_files = OPEN_FILES.get_or_init(Default::default).lock().unwrap();
+    if let Some(pmtiles) = open_files.get(path) {
+        return Ok(pmtiles.clone());
+    }
+    let pmtiles = Arc::new(PmTiles::open(path)?);
+    open_files.insert(path.to_string(), pmtiles.clone());
+    Ok(pmtiles)
+}
+
+pub async fn read_tile(path: &str, coord: (i32, i32, i32)) -> anyhow::Result<Option<TileImage>> {
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || open_file(&path)?.read_tile(coord)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    /// The directory encoding from the spec, the inverse of `parse_directory`.
+    fn serialize_directory(entries: &[DirEntry]) -> Vec<u8> {
+        let mut out = vec![];
+        write_varint(&mut out, entries.len() as u64);
+        let mut last_id = 0;
+        for e in entries {
+            write_varint(&mut out, e.tile_id - last_id);
+            last_id = e.tile_id;
+        }
+        for e in entries {
+            write_varint(&mut out, e.run_length as u64);
+        }
+        for e in entries {
+            write_varint(&mut out, e.length as u64);
+        }
+        for (i, e) in entries.iter().enumerate() {
+            let follows_previous =
+                i > 0 && e.offset == entries[i - 1].offset + entries[i - 1].length as u64;
+            write_varint(&mut out, if follows_previous { 0 } else { e.offset + 1 });
+        }
+        out
+    }
+
+    #[test]
+    fn tile_ids_from_the_spec() {
+        assert_eq!(zxy_to_tile_id(0, 0, 0).unwrap(), 0);
+        assert_eq!(zxy_to_tile_id(1, 0, 0).unwrap(), 1);
+        assert_eq!(zxy_to_tile_id(1, 0, 1).unwrap(), 2);
+        assert_eq!(zxy_to_tile_id(1, 1, 1).unwrap(), 3);
+        assert_eq!(zxy_to_tile_id(1, 1, 0).unwrap(), 4);
+        assert_eq!(zxy_to_tile_id(2, 0, 0).unwrap(), 5);
+        assert_eq!(zxy_to_tile_id(12, 3423, 1763).unwrap(), 19078479);
+    }
+
+    #[test]
+    fn tile_ids_of_a_level_are_a_permutation() {
+        let first = zxy_to_tile_id(3, 0, 0).unwrap();
+        let mut ids = (0..8)
+            .flat_map(|x| (0..8).map(move |y| zxy_to_tile_id(3, x, y).unwrap()))
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, (first..first + 64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn tile_ids_reject_bad_tiles() {
+        assert!(zxy_to_tile_id(26, (1 << 26) - 1, 0).is_ok());
+        assert!(zxy_to_tile_id(27, 0, 0).is_err());
+        assert!(zxy_to_tile_id(40, 0, 0).is_err());
+        assert!(zxy_to_tile_id(-1, 0, 0).is_err());
+        assert!(zxy_to_tile_id(2, -1, 0).is_err());
+        assert!(zxy_to_tile_id(2, 0, -1).is_err());
+        assert!(zxy_to_tile_id(2, 4, 0).is_err());
+        assert!(zxy_to_tile_id(2, 0, 4).is_err());
+    }
+
+    #[test]
+    fn directory_round_trip() {
+        let entries = vec![
+            DirEntry {
+                tile_id: 0,
+                offset: 0,
+                length: 100,
+                run_length: 1,
+            },
+            // directly after the previous entry: stored as offset 0
+            DirEntry {
+                tile_id: 1,
+                offset: 100,
+                length: 50,
+                run_length: 3,
+            },
+            // shares the data of the first tile
+            DirEntry {
+                tile_id: 10,
+                offset: 0,
+                length: 100,
+                run_length: 1,
+            },
+            // a leaf directory
+            DirEntry {
+                tile_id: 300,
+                offset: 70_000,
+                length: 4000,
+                run_length: 0,
+            },
+        ];
+        let parsed = parse_directory(&serialize_directory(&entries)).unwrap();
+        assert_eq!(parsed, entries);
+
+        assert_eq!(find_entry(&parsed, 0), Some(entries[0]));
+        assert_eq!(find_entry(&parsed, 3), Some(entries[1]));
+        assert_eq!(find_entry(&parsed, 4), None);
+        assert_eq!(find_entry(&parsed, 10), Some(entries[2]));
+        assert_eq!(find_entry(&parsed, 5000), Some(entries[3]));
+    }
+
+    #[test]
+    fn malformed_directories_are_errors() {
+        let entry = DirEntry {
+            tile_id: 5,
+            offset: 0,
+            length: 10,
+            run_length: 1,
+        };
+        let mut bytes = serialize_directory(&[entry]);
+        // the first offset is 0 + 1; a 0 there has no previous entry to follow
+        *bytes.last_mut().unwrap() = 0;
+        assert!(parse_directory(&bytes).is_err());
+
+        let bytes = serialize_directory(&[entry]);
+        assert!(parse_directory(&bytes[..bytes.len() - 1]).is_err());
+        // a huge entry count must not allocate before failing
+        let mut bytes = vec![];
+        write_varint(&mut bytes, u64::MAX);
+        assert!(parse_directory(&bytes).is_err());
+    }
+
+    /// An uncompressed archive with a single PNG tile at 0/0/0.
+    fn archive(tile: &[u8]) -> Vec<u8> {
+        let root = serialize_directory(&[DirEntry {
+            tile_id: 0,
+            offset: 0,
+            length: tile.len() as u32,
+            run_length: 1,
+        }]);
+        let mut header = vec![0u8; HEADER_LEN];
+        header[0..7].copy_from_slice(b"PMTiles");
+        header[7] = 3;
+        let mut put_u64 = |at: usize, value: u64| {
+            header[at..at + 8].copy_from_slice(&value.to_le_bytes());
+        };
+        put_u64(8, HEADER_LEN as u64);
+        put_u64(16, root.len() as u64);
+        put_u64(40, (HEADER_LEN + root.len()) as u64);
+        put_u64(56, (HEADER_LEN + root.len()) as u64);
+        put_u64(64, tile.len() as u64);
+        header[97] = 1; // no compression
+        header[98] = 1;
+        header[99] = 2; // png
+        header[100] = 0;
+        header[101] = 2;
+        [header, root, tile.to_vec()].concat()
+    }
+
+    fn open_bytes(name: &str, bytes: &[u8]) -> anyhow::Result<PmTiles> {
+        let path =
+            std::env::temp_dir().join(format!("pmtiles_{name}_{}.pmtiles", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let pmtiles = PmTiles::open(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        pmtiles
+    }
+
+    #[test]
+    fn archive_reads_its_tile() {
+        let tile = b"\x89PNG tile";
+        let pmtiles = open_bytes("ok", &archive(tile)).unwrap();
+        let img = pmtiles.read_tile((0, 0, 0)).unwrap().unwrap();
+        assert_eq!(img.bytes, tile);
+        assert_eq!(img.content_type, "image/png");
+        assert!(pmtiles.read_tile((1, 0, 0)).unwrap().is_none());
+        assert!(pmtiles.read_tile((3, 0, 0)).unwrap().is_none());
+    }
+
+    #[test]
+    fn overlong_and_truncated_archives_are_errors() {
+        let bytes = archive(b"\x89PNG tile");
+        assert!(open_bytes("short_header", &bytes[..HEADER_LEN - 10]).is_err());
+
+        // a root directory length of a terabyte must fail before allocating it
+        let mut overlong = bytes.clone();
+        overlong[16..24].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert!(open_bytes("overlong_root", &overlong).is_err());
+        // just past the end of the file
+        let mut overlong = bytes.clone();
+        overlong[16..24].copy_from_slice(&(bytes.len() as u64).to_le_bytes());
+        assert!(open_bytes("past_end", &overlong).is_err());
+        // an offset that wraps around u64
+        let mut wrapping = bytes.clone();
+        wrapping[56..64].copy_from_slice(&u64::MAX.to_le_bytes());
+        let pmtiles = open_bytes("wrapping", &wrapping).unwrap();
+        assert!(pmtiles.read_tile((0, 0, 0)).is_err());
+
+        // the tile data cut short
+        let pmtiles = open_bytes("truncated", &bytes[..bytes.len() - 3]).unwrap();
+        assert!(pmtiles.read_tile((0, 0, 0)).is_err());
+    }
+}
@@ -0,0 +1,401 @@
+//! Disk cache for upstream tile images, shared by every client of this server.
+//!
+//! Each tile is one file at `<dir>/<provider>/<z>/<x>/<y>.tile`: the content type,
+//! a newline, then the raw image bytes. Entries older than the TTL are dropped on
+//! read, and the least recently used ones are evicted once the size limit is hit.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use dioxus_logger::tracing::{info, warn};
+
+use crate::tile_provider::TileImage;
+
+const DEFAULT_CACHE_DIR: &str = "tile_cache";
+const DEFAULT_MAX_MB: u64 = 1024;
+const DEFAULT_TTL_HOURS: u64 = 24 * 30;
+/// print hit/miss counts every this many lookups
+const LOG_EVERY: u64 = 500;
+
+struct CacheEntry {
+    size: u64,
+    created: SystemTime,
+    last_used: SystemTime,
+}
+
+#[derive(Default)]
+struct CacheIndex {
+    entries: HashMap<PathBuf, CacheEntry>,
+    total_bytes: u64,
+}
+
+pub struct TileCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    ttl: Duration,
+    index: Mutex<CacheIndex>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Configured with `TILE_CACHE_DIR`, `TILE_CACHE_MAX_MB` and `TILE_CACHE_TTL_HOURS`.
+pub fn tile_cache() -> &'static TileCache {
+    static CACHE: OnceLock<TileCache> = OnceLock::new();
+    CACHE.get_or_init(|| {
+        let env_u64 = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        let dir = std::env::var("TILE_CACHE_DIR").unwrap_or_else(|_| DEFAULT_CACHE_DIR.into());
+        TileCache::open(
+            PathBuf::from(dir),
+            env_u64("TILE_CACHE_MAX_MB", DEFAULT_MAX_MB) * 1024 * 1024,
+            Duration::from_secs(env_u64("TILE_CACHE_TTL_HOURS", DEFAULT_TTL_HOURS) * 3600),
+        )
+    })
+}
+
+impl TileCache {
+    pub fn open(dir: PathBuf, max_bytes: u64, ttl: Duration) -> Self {
+        let mut index = CacheIndex::default();
+        scan_dir(&dir, &mut index);
+        info!(
+            "tile cache: {} tiles / {} MB found in {:?}",
+            index.entries.len(),
+            index.total_bytes / 1024 / 1024,
+            dir
+        );
+        let cache = Self {
+            dir,
+            max_bytes,
+            ttl,
+            index: Mutex::new(index),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        };
+        remove_files(&cache.evict());
+        cache
+    }
+
+    fn tile_path(&self, provider_id: &str, coord: (i32, i32, i32)) -> PathBuf {
+        let (sq_z, sq_x, sq_y) = coord;
+        self.dir
+            .join(provider_id)
+            .join(sq_z.to_string())
+            .join(sq_x.to_string())
+            .join(format!("{sq_y}.tile"))
+    }
+
+    pub async fn get(&self, provider_id: &str, coord: (i32, i32, i32)) -> Option<TileImage> {
+        self.get_at(provider_id, coord, SystemTime::now()).await
+    }
+
+    /// [`Self::get`] as if the clock read `now`.
+    async fn get_at(
+        &self,
+        provider_id: &str,
+        coord: (i32, i32, i32),
+        now: SystemTime,
+    ) -> Option<TileImage> {
+        let path = self.tile_path(provider_id, coord);
+        let fresh = {
+            let mut index = self.index.lock().unwrap();
+            match index.entries.get_mut(&path) {
+                None => None,
+                Some(entry) if now.duration_since(entry.created).unwrap_or_default() > self.ttl => {
+                    Some(false)
+                }
+                Some(entry) => {
+                    entry.last_used = now;
+                    Some(true)
+                }
+            }
+        };
+        let img = match fresh {
+            Some(true) => tokio::task::spawn_blocking(move || read_tile_file(&path))
+                .await
+                .ok()
+                .flatten(),
+            Some(false) => {
+                self.remove(path).await;
+                None
+            }
+            None => None,
+        };
+        self.count_lookup(img.is_some());
+        img
+    }
+
+    pub async fn put(&self, provider_id: &str, coord: (i32, i32, i32), img: &TileImage) {
+        self.put_at(provider_id, coord, img, SystemTime::now())
+            .await
+    }
+
+    /// [`Self::put`] as if the clock read `now`.
+    async fn put_at(
+        &self,
+        provider_id: &str,
+        coord: (i32, i32, i32),
+        img: &TileImage,
+        now: SystemTime,
+    ) {
+        let path = self.tile_path(provider_id, coord);
+        let mut contents = Vec::with_capacity(img.content_type.len() + 1 + img.bytes.len());
+        contents.extend_from_slice(img.content_type.as_bytes());
+        contents.push(b'\n');
+        contents.extend_from_slice(&img.bytes);
+        let size = contents.len() as u64;
+
+        let write_path = path.clone();
+        let written = tokio::task::spawn_blocking(move || {
+            if let Some(parent) = write_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&write_path, contents)
+        })
+        .await;
+        match written {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                warn!("tile cache: failed to write {path:?}: {e}");
+                return;
+            }
+            Err(e) => {
+                warn!("tile cache: write task failed for {path:?}: {e}");
+                return;
+            }
+        }
+
+        {
+            let mut index = self.index.lock().unwrap();
+            let old = index.entries.insert(
+                path,
+                CacheEntry {
+                    size,
+                    created: now,
+                    last_used: now,
+                },
+            );
+            if let Some(old) = old {
+                index.total_bytes -= old.size;
+            }
+            index.total_bytes += size;
+        }
+        let evicted = self.evict();
+        if !evicted.is_empty() {
+            let _ = tokio::task::spawn_blocking(move || remove_files(&evicted)).await;
+        }
+    }
+
+    async fn remove(&self, path: PathBuf) {
+        {
+            let mut index = self.index.lock().unwrap();
+            if let Some(entry) = index.entries.remove(&path) {
+                index.total_bytes -= entry.size;
+            }
+        }
+        let _ = tokio::task::spawn_blocking(move || remove_files(&[path])).await;
+    }
+
+    /// Drops least recently used tiles from the index until the cache is back under
+    /// 90% of the limit. Returns their files, to be deleted without holding the lock.
+    fn evict(&self) -> Vec<PathBuf> {
+        let mut index = self.index.lock().unwrap();
+        if index.total_bytes <= self.max_bytes {
+            return vec![];
+        }
+        let target = self.max_bytes / 10 * 9;
+        let mut by_age = index
+            .entries
+            .iter()
+            .map(|(path, entry)| (entry.last_used, path.clone()))
+            .collect::<Vec<_>>();
+        by_age.sort();
+
+        let mut evicted = vec![];
+        for (_, path) in by_age {
+            if index.total_bytes <= target {
+                break;
+            }
+            if let Some(entry) = index.entries.remove(&path) {
+                index.total_bytes -= entry.size;
+            }
+            evicted.push(path);
+        }
+        info!(
+            "tile cache: evicting {} tiles, {} MB left.",
+            evicted.len(),
+            index.total_bytes / 1024 / 1024
+        );
+        evicted
+    }
+
+    fn count_lookup(&self, hit: bool) {
+        let (hits, misses) = if hit {
+            (
+                self.hits.fetch_add(1, Ordering::Relaxed) + 1,
+                self.misses.load(Ordering::Relaxed),
+            )
+        } else {
+            (
+                self.hits.load(Ordering::Relaxed),
+                self.misses.fetch_add(1, Ordering::Relaxed) + 1,
+            )
+        };
+        if (hits + misses) % LOG_EVERY == 0 {
+            self.log_stats();
+        }
+    }
+
+    pub fn log_stats(&self) {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let (count, total_bytes) = {
+            let index = self.index.lock().unwrap();
+            (index.entries.len(), index.total_bytes)
+        };
+        info!(
+            "tile cache: {hits} hits / {misses} misses; {count} tiles, {} MB.",
+            total_bytes / 1024 / 1024
+        );
+    }
+}
+
+fn remove_files(paths: &[PathBuf]) {
+    for path in paths {
+        if let Err(e) = std::fs::remove_file(path) {
+            warn!("tile cache: failed to remove {path:?}: {e}");
+        }
+    }
+}
+
+fn read_tile_file(path: &Path) -> Option<TileImage> {
+    let contents = std::fs::read(path).ok()?;
+    let newline_pos = contents.iter().position(|b| *b == b'\n')?;
+    let content_type = String::from_utf8(contents[..newline_pos].to_vec()).ok()?;
+    Some(TileImage {
+        content_type,
+        bytes: contents[(newline_pos + 1)..].to_vec(),
+    })
+}
+
+fn scan_dir(dir: &Path, index: &mut CacheIndex) {
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for item in read_dir.flatten() {
+        let path = item.path();
+        let Ok(metadata) = item.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            scan_dir(&path, index);
+        } else if path.extension().is_some_and(|e| e == "tile") {
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            index.total_bytes += metadata.len();
+            index.entries.insert(
+                path,
+                CacheEntry {
+                    size: metadata.len(),
+                    created: modified,
+                    last_used: modified,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tile_cache_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    fn tile(len: usize) -> TileImage {
+        TileImage {
+            content_type: "image/png".into(),
+            bytes: vec![7; len],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = temp_dir("round_trip");
+        let cache = TileCache::open(dir.clone(), 1024 * 1024, Duration::from_secs(3600));
+        block_on(async {
+            assert!(cache.get("osm", (1, 0, 1)).await.is_none());
+            cache.put("osm", (1, 0, 1), &tile(10)).await;
+            let img = cache.get("osm", (1, 0, 1)).await.unwrap();
+            assert_eq!(img.content_type, "image/png");
+            assert_eq!(img.bytes, vec![7; 10]);
+            assert!(cache.get("other", (1, 0, 1)).await.is_none());
+        });
+        // a restarted server finds the tile on disk
+        let reopened = TileCache::open(dir.clone(), 1024 * 1024, Duration::from_secs(3600));
+        assert!(block_on(reopened.get("osm", (1, 0, 1))).is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// A fixed point in time plus `secs`, for a clock the tests control.
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs)
+    }
+
+    #[test]
+    fn expired_tiles_are_dropped() {
+        let dir = temp_dir("ttl");
+        let cache = TileCache::open(dir.clone(), 1024 * 1024, Duration::from_secs(60));
+        block_on(async {
+            cache.put_at("osm", (2, 1, 1), &tile(10), at(0)).await;
+            assert!(cache.get_at("osm", (2, 1, 1), at(60)).await.is_some());
+            assert!(cache.get_at("osm", (2, 1, 1), at(61)).await.is_none());
+        });
+        assert!(!cache.tile_path("osm", (2, 1, 1)).exists());
+        assert_eq!(cache.index.lock().unwrap().total_bytes, 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn least_recently_used_tiles_are_evicted_first() {
+        let dir = temp_dir("lru");
+        // every file is the content type, a newline and 100 bytes: room for three
+        let file_size = "image/png\n".len() as u64 + 100;
+        let cache = TileCache::open(dir.clone(), file_size * 3, Duration::from_secs(3600));
+        block_on(async {
+            for x in 0..3 {
+                cache
+                    .put_at("osm", (3, x, 0), &tile(100), at(x as u64))
+                    .await;
+            }
+            // touch the oldest, so the second one is now the least recently used
+            assert!(cache.get_at("osm", (3, 0, 0), at(10)).await.is_some());
+            cache.put_at("osm", (3, 3, 0), &tile(100), at(20)).await;
+
+            // down to 90% of the limit: two tiles go
+            assert!(cache.get_at("osm", (3, 1, 0), at(30)).await.is_none());
+            assert!(cache.get_at("osm", (3, 2, 0), at(30)).await.is_none());
+            assert!(cache.get_at("osm", (3, 0, 0), at(30)).await.is_some());
+            assert!(cache.get_at("osm", (3, 3, 0), at(30)).await.is_some());
+        });
+        assert!(!cache.tile_path("osm", (3, 1, 0)).exists());
+        assert!(!cache.tile_path("osm", (3, 2, 0)).exists());
+        assert_eq!(cache.index.lock().unwrap().total_bytes, file_size * 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,315 @@
+//! Framing for the `get_tile_list` byte stream.
+//!
+//! Every frame is a big-endian `u32` length followed by that many bytes of CBOR
+//! encoding one [`TileFrame`].
+
+use serde::{Deserialize, Serialize};
+
+pub const PROTOCOL_VERSION: u16 = 4;
+/// Refuse frames bigger than this instead of buffering forever on a corrupt length.
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+const LEN_PREFIX: usize = 4;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TileFrame {
+    pub version: u16,
+    pub msg: TileMsg,
+}
+
+/// The part of a [`TileFrame`] every protocol version shares, read before the message
+/// so that a frame from another version is reported as such, not as garbage.
+#[derive(Deserialize)]
+struct FrameHeader {
+    version: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TileMsg {
+    /// keeps the connection alive while the server waits on upstream
+    Ping,
+    Tile {
+        coord: (i32, i32, i32),
+        /// HTTP status the tile is served with, 2xx; failures are sent as `Error`
+        status: u16,
+        content_type: String,
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    Error {
+        coord: (i32, i32, i32),
+        error: TileError,
+    },
+}
+
+/// Why the server could not produce a tile.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TileError {
+    /// upstream answered with an unexpected HTTP status
+    UpstreamStatus(u16),
+    Timeout,
+    /// upstream answered, but not with an image
+    Decode(String),
+    /// the source has no imagery for this tile
+    NotFound,
+    RateLimited {
+        retry_after_secs: Option<u64>,
+    },
+    /// connection errors, local file errors, bad config
+    Other(String),
+}
+
+impl TileError {
+    /// `false` when asking again for the same tile gives the same answer.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::NotFound | Self::Decode(_) => false,
+            Self::UpstreamStatus(status) => *status >= 500,
+            Self::Timeout | Self::RateLimited { .. } | Self::Other(_) => true,
+        }
+    }
+}
+
+impl std::fmt::Display for TileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UpstreamStatus(status) => write!(f, "upstream answered HTTP {status}"),
+            Self::Timeout => write!(f, "upstream timed out"),
+            Self::Decode(err) => write!(f, "not an image: {err}"),
+            Self::NotFound => write!(f, "no imagery"),
+            Self::RateLimited {
+                retry_after_secs: Some(secs),
+            } => write!(f, "rate limited, retry after {secs}s"),
+            Self::RateLimited {
+                retry_after_secs: None,
+            } => write!(f, "rate limited"),
+            Self::Other(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    TooLarge(usize),
+    Decode(String),
+    Encode(String),
+    VersionMismatch { expected: u16, got: u16 },
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLarge(len) => write!(f, "frame of {len} bytes is over {MAX_FRAME_LEN}"),
+            Self::Decode(err) => write!(f, "failed to decode frame: {err}"),
+            Self::Encode(err) => write!(f, "failed to encode frame: {err}"),
+            Self::VersionMismatch { expected, got } => {
+                write!(f, "protocol version {got}, expected {expected}")
+            }
+        }
+    }
+}
+
+impl TileFrame {
+    pub fn new(msg: TileMsg) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            msg,
+        }
+    }
+
+    /// Length prefix + CBOR body, ready to be written to the stream.
+    pub fn encode(&self) -> Result<Vec<u8>, FrameError> {
+        let mut out = vec![0u8; LEN_PREFIX];
+        ciborium::into_writer(self, &mut out).map_err(|e| FrameError::Encode(e.to_string()))?;
+        let len = out.len() - LEN_PREFIX;
+        if len > MAX_FRAME_LEN {
+            return Err(FrameError::TooLarge(len));
+        }
+        out[..LEN_PREFIX].copy_from_slice(&(len as u32).to_be_bytes());
+        Ok(out)
+    }
+}
+
+/// Collects stream chunks and cuts them into frames; chunk and frame
+/// boundaries do not have to line up.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// `None` until a whole frame is buffered. A frame that fails to decode is
+    /// dropped, so the next call continues with the frame after it.
+    pub fn next_frame(&mut self) -> Option<Result<TileFrame, FrameError>> {
+        if self.buf.len() < LEN_PREFIX {
+            return None;
+        }
+        let len = u32::from_be_bytes(self.buf[..LEN_PREFIX].try_into().unwrap()) as usize;
+        if len > MAX_FRAME_LEN {
+            // the length itself is garbage - nothing after it can be trusted
+            self.buf.clear();
+            return Some(Err(FrameError::TooLarge(len)));
+        }
+        if self.buf.len() < LEN_PREFIX + len {
+            return None;
+        }
+        let body = self
+            .buf
+            .drain(..LEN_PREFIX + len)
+            .skip(LEN_PREFIX)
+            .collect::<Vec<_>>();
+        let header = match ciborium::from_reader::<FrameHeader, _>(body.as_slice()) {
+            Ok(header) => header,
+            Err(e) => return Some(Err(FrameError::Decode(e.to_string()))),
+        };
+        if header.version != PROTOCOL_VERSION {
+            return Some(Err(FrameError::VersionMismatch {
+                expected: PROTOCOL_VERSION,
+                got: header.version,
+            }));
+        }
+        Some(
+            ciborium::from_reader::<TileFrame, _>(body.as_slice())
+                .map_err(|e| FrameError::Decode(e.to_string())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(x: i32) -> TileFrame {
+        TileFrame::new(TileMsg::Tile {
+            coord: (3, x, 2),
+            status: 200,
+            content_type: "image/png".into(),
+            data: vec![x as u8; 300],
+        })
+    }
+
+    fn error(x: i32) -> TileFrame {
+        TileFrame::new(TileMsg::Error {
+            coord: (3, x, 2),
+            error: TileError::RateLimited {
+                retry_after_secs: Some(7),
+            },
+        })
+    }
+
+    fn frame(bytes: &[u8]) -> Vec<u8> {
+        let mut out = (bytes.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(bytes);
+        out
+    }
+
+    #[test]
+    fn frame_split_across_chunks() {
+        let bytes = tile(1).encode().unwrap();
+        let mut decoder = FrameDecoder::default();
+        // the first cut falls inside the length prefix
+        let (a, rest) = bytes.split_at(2);
+        let (b, c) = rest.split_at(100);
+        decoder.push(a);
+        assert!(decoder.next_frame().is_none());
+        decoder.push(b);
+        assert!(decoder.next_frame().is_none());
+        decoder.push(c);
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), tile(1));
+        assert!(decoder.next_frame().is_none());
+    }
+
+    #[test]
+    fn two_frames_in_one_chunk() {
+        let mut bytes = tile(1).encode().unwrap();
+        bytes.extend(error(2).encode().unwrap());
+        bytes.extend(TileFrame::new(TileMsg::Ping).encode().unwrap()[..3].iter());
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&bytes);
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), tile(1));
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), error(2));
+        // the start of a third one stays buffered
+        assert!(decoder.next_frame().is_none());
+    }
+
+    #[test]
+    fn truncated_length_prefix_waits() {
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&[0, 0, 1]);
+        assert!(decoder.next_frame().is_none());
+        assert_eq!(decoder.buf.len(), 3);
+    }
+
+    #[test]
+    fn oversized_length_is_an_error() {
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes());
+        decoder.push(&[0; 16]);
+        assert!(matches!(
+            decoder.next_frame(),
+            Some(Err(FrameError::TooLarge(len))) if len == MAX_FRAME_LEN + 1
+        ));
+        assert!(decoder.next_frame().is_none());
+    }
+
+    #[test]
+    fn invalid_cbor_is_skipped() {
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&frame(&[0xff, 0x00, 0x13]));
+        decoder.push(&tile(4).encode().unwrap());
+        assert!(matches!(
+            decoder.next_frame(),
+            Some(Err(FrameError::Decode(_)))
+        ));
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), tile(4));
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let mut old = tile(5);
+        old.version = PROTOCOL_VERSION - 1;
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&old.encode().unwrap());
+        assert!(matches!(
+            decoder.next_frame(),
+            Some(Err(FrameError::VersionMismatch { got, .. })) if got == PROTOCOL_VERSION - 1
+        ));
+    }
+
+    #[test]
+    fn newer_messages_are_a_version_mismatch() {
+        // a later version with a message this one does not know
+        #[derive(Serialize)]
+        enum NewerMsg {
+            Vector {
+                coord: (i32, i32, i32),
+                layers: Vec<String>,
+            },
+        }
+        #[derive(Serialize)]
+        struct NewerFrame {
+            version: u16,
+            msg: NewerMsg,
+        }
+        let mut body = vec![];
+        let newer = NewerFrame {
+            version: PROTOCOL_VERSION + 1,
+            msg: NewerMsg::Vector {
+                coord: (1, 0, 0),
+                layers: vec!["roads".into()],
+            },
+        };
+        ciborium::into_writer(&newer, &mut body).unwrap();
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&frame(&body));
+        decoder.push(&tile(6).encode().unwrap());
+        assert!(matches!(
+            decoder.next_frame(),
+            Some(Err(FrameError::VersionMismatch { got, .. })) if got == PROTOCOL_VERSION + 1
+        ));
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), tile(6));
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+
+use dioxus::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Used when a provider does not set its own `user_agent`.
+pub const DEFAULT_USER_AGENT: &str =
+    "Mozilla/5.0 (X11; Ubuntu; Linux i686; rv:133.0) Gecko/20100101 Firefox/133.0";
+
+/// Provider the client asks for until the user picks another one.
+pub const DEFAULT_TILE_PROVIDER: &str = "google_satellite";
+
+/// One tile source the server can serve imagery from.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TileProvider {
+    pub id: String,
+    pub name: String,
+    #[serde(default = "default_max_zoom")]
+    pub max_zoom: i32,
+    #[serde(default)]
+    pub attribution: String,
+    /// pixel size of the tile images: 256, or 512 for providers that serve big tiles.
+    #[serde(default = "default_tile_size")]
+    pub tile_size: u32,
+    #[serde(flatten, deserialize_with = "deserialize_tile_source")]
+    pub source: TileSource,
+}
+
+fn default_max_zoom() -> i32 {
+    crate::_const::MAX_Z
+}
+
+fn default_tile_size() -> u32 {
+    256
+}
+
+/// Where the tiles of a [`TileProvider`] come from, picked by `"type"` in the config.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TileSource {
+    Http(HttpTileSource),
+    Mbtiles(MbTilesSource),
+    Pmtiles(PmTilesSource),
+}
+
+/// Provider configs written before sources had a `"type"` are all http.
+fn deserialize_tile_source<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<TileSource, D::Error> {
+    let mut value = serde_json::Value::deserialize(deserializer)?;
+    if let Some(fields) = value.as_object_mut() {
+        fields.entry("type").or_insert_with(|| "http".into());
+    }
+    TileSource::deserialize(value).map_err(serde::de::Error::custom)
+}
+
+impl TileSource {
+    /// Local files are neither retried nor copied into the disk cache.
+    pub fn is_local(&self) -> bool {
+        !matches!(self, TileSource::Http(_))
+    }
+
+    /// The server file the tiles are read from, for local sources.
+    pub fn local_path(&self) -> Option<&str> {
+        match self {
+            TileSource::Http(_) => None,
+            TileSource::Mbtiles(source) => Some(&source.path),
+            TileSource::Pmtiles(source) => Some(&source.path),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HttpTileSource {
+    /// `{z}`, `{x}` and `{y}` are replaced with the tile coordinates,
+    /// `{s}` with one of the `subdomains`, `{r}` with `@2x` when `retina` is set.
+    pub url_template: String,
+    #[serde(default)]
+    pub subdomains: Vec<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// if set, every downloaded tile is also written into this MBTiles file.
+    #[serde(default)]
+    pub mbtiles_sink: Option<String>,
+    /// always ask for the double resolution `@2x` images. Clients on plain screens
+    /// then use tiles one zoom level up, so they download about the same.
+    #[serde(default)]
+    pub retina: bool,
+}
+
+impl HttpTileSource {
+    pub fn tile_url(&self, coord: (i32, i32, i32)) -> String {
+        let (sq_z, sq_x, sq_y) = coord;
+        let mut url = self
+            .url_template
+            .replace("{z}", &sq_z.to_string())
+            .replace("{x}", &sq_x.to_string())
+            .replace("{y}", &sq_y.to_string())
+            .replace("{r}", if self.retina { "@2x" } else { "" });
+        if !self.subdomains.is_empty() {
+            // same tile always hits the same subdomain, so upstream caches stay warm
+            let idx = (sq_x + sq_y).rem_euclid(self.subdomains.len() as i32) as usize;
+            url = url.replace("{s}", &self.subdomains[idx]);
+        }
+        url
+    }
+
+    pub fn user_agent(&self) -> &str {
+        self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT)
+    }
+}
+
+/// Tiles read from an MBTiles (SQLite) file on the server.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MbTilesSource {
+    pub path: String,
+}
+
+/// Tiles read from a PMTiles v3 archive on the server.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PmTilesSource {
+    pub path: String,
+}
+
+impl TileProvider {
+    pub fn info(&self) -> TileProviderInfo {
+        TileProviderInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            max_zoom: self.max_zoom,
+            attribution: self.attribution.clone(),
+            tile_px: self.image_px(),
+        }
+    }
+
+    /// Pixel size of the images actually served, with `@2x` counted in.
+    pub fn image_px(&self) -> u32 {
+        match &self.source {
+            TileSource::Http(http) if http.retina => self.tile_size * 2,
+            _ => self.tile_size,
+        }
+    }
+}
+
+/// Raw image bytes of one tile, as sent by the upstream server.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TileImage {
+    pub content_type: String,
+    pub bytes: Vec<u8>,
+}
+
+impl TileImage {
+    /// Guesses the content type from the magic bytes, for sources that store no headers.
+    pub fn from_bytes(bytes: Vec<u8>, fallback_content_type: &str) -> Self {
+        let content_type = if bytes.starts_with(b"\x89PNG") {
+            "image/png"
+        } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            "image/jpeg"
+        } else if bytes.len() > 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            "image/webp"
+        } else if bytes.starts_with(b"GIF8") {
+            "image/gif"
+        } else {
+            fallback_content_type
+        };
+        Self {
+            content_type: content_type.to_string(),
+            bytes,
+        }
+    }
+
+    pub fn to_data_url(&self) -> String {
+        use base64::Engine;
+        let b64 = base64::prelude::BASE64_STANDARD.encode(&self.bytes);
+        format!("data:{};base64,{}", self.content_type, b64)
+    }
+}
+
+/// The part of a [`TileProvider`] the client is allowed to see.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TileProviderInfo {
+    pub id: String,
+    pub name: String,
+    pub max_zoom: i32,
+    pub attribution: String,
+    /// see [`TileProvider::image_px`]
+    pub tile_px: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TileProviderRegistry {
+    pub providers: Vec<TileProvider>,
+}
+
+impl TileProviderRegistry {
+    pub fn get(&self, id: &str) -> Option<&TileProvider> {
+        self.providers.iter().find(|p| p.id == id)
+    }
+
+    /// Used when no config file is present on the server: the `tile_providers.json` shipped
+    /// with the crate, built into the binary.
+    pub fn builtin() -> Self {
+        serde_json::from_str(include_str!("../tile_providers.json"))
+            .expect("bundled tile_providers.json is valid")
+    }
+}
+
+/// Path of the provider config file, overridable with `TILE_PROVIDERS_CONFIG`.
+#[cfg(feature = "server")]
+const TILE_PROVIDERS_CONFIG: &str = "tile_providers.json";
+
+#[cfg(feature = "server")]
+pub fn tile_provider_registry() -> &'static TileProviderRegistry {
+    static REGISTRY: std::sync::OnceLock<TileProviderRegistry> = std::sync::OnceLock::new();
+    REGISTRY.get_or_init(load_registry)
+}
+
+#[cfg(feature = "server")]
+fn load_registry() -> TileProviderRegistry {
+    use dioxus_logger::tracing::{error, info, warn};
+
+    let path = std::env::var("TILE_PROVIDERS_CONFIG")
+        .unwrap_or_else(|_| TILE_PROVIDERS_CONFIG.to_string());
+    let mut registry = match std::fs::read_to_string(&path) {
+        Ok(text) => match serde_json::from_str::<TileProviderRegistry>(&text) {
+            Ok(registry) => {
+                info!(
+                    "loaded {} tile providers from {path:?}.",
+                    registry.providers.len()
+                );
+                registry
+            }
+            Err(e) => {
+                error!("bad tile provider config {path:?}: {e}; using builtin providers.");
+                TileProviderRegistry::builtin()
+            }
+        },
+        Err(e) => {
+            info!("no tile provider config at {path:?} ({e}), using builtin providers.");
+            TileProviderRegistry::builtin()
+        }
+    };
+    // a layer whose file is not there would only ever show errors
+    registry
+        .providers
+        .retain(|provider| match provider.source.local_path() {
+            Some(file) if !std::path::Path::new(file).exists() => {
+                warn!(
+                    "tile provider {:?}: no file at {file:?}, skipped.",
+                    provider.id
+                );
+                false
+            }
+            _ => true,
+        });
+    registry
+}
+
+#[server]
+pub async fn get_tile_providers() -> Result<Vec<TileProviderInfo>, ServerFnError> {
+    Ok(tile_provider_registry()
+        .providers
+        .iter()
+        .map(TileProvider::info)
+        .collect())
+}
+
+pub type TileProvidersResource = Resource<Result<Vec<TileProviderInfo>, ServerFnError>>;
+
+/// Fetches the provider list once for the whole app; read it with `use_tile_providers`.
+pub fn init_tile_providers() {
+    let providers = use_resource(get_tile_providers);
+    use_context_provider::<TileProvidersResource>(|| providers);
+}
+
+pub fn use_tile_providers() -> TileProvidersResource {
+    use_context::<TileProvidersResource>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_config_parses() {
+        let registry = TileProviderRegistry::builtin();
+        assert!(registry.get(DEFAULT_TILE_PROVIDER).is_some());
+        assert!(registry.get("osm").is_some());
+    }
+
+    #[test]
+    fn local_sources_name_their_file() {
+        let registry = TileProviderRegistry::builtin();
+        let path = |id: &str| registry.get(id).unwrap().source.local_path();
+        assert_eq!(path("offline"), Some("offline/local.mbtiles"));
+        assert_eq!(path("region"), Some("offline/region.pmtiles"));
+        assert_eq!(path("osm"), None);
+    }
+
+    #[test]
+    fn providers_without_type_are_http() {
+        let provider: TileProvider = serde_json::from_str(
+            r#"{
+                "id": "osm",
+                "name": "Street",
+                "url_template": "https://tile.openstreetmap.org/{z}/{x}/{y}.png"
+            }"#,
+        )
+        .unwrap();
+        assert!(matches!(provider.source, TileSource::Http(_)));
+        assert!(serde_json::from_str::<TileProvider>(
+            r#"{"id": "x", "name": "X", "type": "ftp", "path": "a"}"#
+        )
+        .is_err());
+    }
+}
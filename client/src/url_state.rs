@@ -1,32 +1,91 @@
 #[allow(non_snake_case)]
-use std::{fmt::Display, str::FromStr};
+use std::{borrow::Cow, fmt::Display, str::FromStr};
 
 use base64::engine::general_purpose::URL_SAFE;
 use base64::Engine;
 
 use serde::{Deserialize, Serialize};
 
+use crate::_const::{MAX_Z, MIN_Z};
+use crate::geometry::{lat_lon_to_world, world_to_lat_lon};
+use crate::tile_provider::DEFAULT_TILE_PROVIDER;
+
 // You can use a custom type with the hash segment as long as it implements Display, FromStr and Default
 #[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
 pub struct MapState {
     /// if false, overwrite with the default value with "true" set.
     pub is_init: bool,
     pub zoom: f64,
+    /// world point at the screen center, in tiles at zoom `REF_Z`;
+    /// see `geometry::lat_lon_to_world` / `geometry::world_to_lat_lon`.
     pub pos: (f64, f64),
+    /// tile provider id drawn under everything else.
+    #[serde(default = "default_base_layer")]
+    pub base_layer: Cow<'static, str>,
+    /// drawn on top of the base layer, first one at the bottom.
+    #[serde(default)]
+    pub overlays: Vec<OverlayLayer>,
+    /// compass direction at the top of the screen, degrees clockwise from north.
+    #[serde(default)]
+    pub bearing: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OverlayLayer {
+    pub id: String,
+    /// 0.0 (invisible) ..= 1.0 (opaque)
+    pub opacity: f64,
+}
+
+impl MapState {
+    /// `(layer id, opacity)` from the bottom up: the base layer, then the overlays.
+    pub fn layers(&self) -> Vec<(String, f64)> {
+        std::iter::once((self.base_layer.to_string(), 1.0))
+            .chain(self.overlays.iter().map(|o| (o.id.clone(), o.opacity)))
+            .collect()
+    }
+}
+
+fn default_base_layer() -> Cow<'static, str> {
+    Cow::Borrowed(DEFAULT_TILE_PROVIDER)
 }
 
 pub const INIT_STATE: MapState = MapState {
     is_init: true,
     zoom: 14.1,
     pos: (150063.61456866315, 94921.7548560014),
+    base_layer: Cow::Borrowed(DEFAULT_TILE_PROVIDER),
+    overlays: Vec::new(),
+    bearing: 0.0,
 };
 
-// Display the state in a way that can be parsed by FromStr
+/// Decimals written for latitude and longitude; 7 is about a centimeter.
+const LAT_LON_DECIMALS: usize = 7;
+
+// Display the state in a way that can be parsed by FromStr:
+// `zoom/lat/lon`, then `/base[+overlay@opacity...]` when the layers are not the default ones,
+// then `/bearing` when the map is turned (the layers are written too then, to keep the order).
 impl Display for MapState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut serialized = Vec::new();
-        if ciborium::into_writer(self, &mut serialized).is_ok() {
-            write!(f, "{}", URL_SAFE.encode(serialized))?;
+        let (lat, lon) = world_to_lat_lon(self.pos);
+        let lon = (lon + 180.0).rem_euclid(360.0) - 180.0;
+        write!(
+            f,
+            "{:.2}/{lat:.prec$}/{lon:.prec$}",
+            self.zoom,
+            prec = LAT_LON_DECIMALS
+        )?;
+        let bearing = self.bearing.rem_euclid(360.0);
+        // 359.96 would be written as 360.0
+        let is_turned = (0.05..359.95).contains(&bearing);
+        if self.base_layer != DEFAULT_TILE_PROVIDER || !self.overlays.is_empty() || is_turned {
+            write!(f, "/{}", self.base_layer)?;
+            for overlay in self.overlays.iter() {
+                write!(f, "+{}@{:.2}", overlay.id, overlay.opacity)?;
+            }
+        }
+        if is_turned {
+            write!(f, "/{bearing:.1}")?;
         }
         Ok(())
     }
@@ -35,6 +94,7 @@ impl Display for MapState {
 pub enum StateParseError {
     DecodeError(base64::DecodeError),
     CiboriumError(ciborium::de::Error<std::io::Error>),
+    FormatError(String),
 }
 
 impl std::fmt::Display for StateParseError {
@@ -42,15 +102,20 @@ impl std::fmt::Display for StateParseError {
         match self {
             Self::DecodeError(err) => write!(f, "Failed to decode base64: {}", err),
             Self::CiboriumError(err) => write!(f, "Failed to deserialize: {}", err),
+            Self::FormatError(err) => write!(f, "Failed to parse map position: {}", err),
         }
     }
 }
 
-// Parse the state from a string that was created by Display
+// Parse the state from a string that was created by Display, or from the
+// base64 CBOR that older links carry. Base64 never contains a `/`.
 impl FromStr for MapState {
     type Err = StateParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('/') {
+            return parse_readable(s);
+        }
         let decompressed = URL_SAFE
             .decode(s.as_bytes())
             .map_err(StateParseError::DecodeError)?;
@@ -59,3 +124,151 @@ impl FromStr for MapState {
         Ok(parsed)
     }
 }
+
+fn parse_readable(s: &str) -> Result<MapState, StateParseError> {
+    let parts = s.split('/').collect::<Vec<_>>();
+    if !(3..=5).contains(&parts.len()) {
+        return Err(StateParseError::FormatError(format!(
+            "expected zoom/lat/lon[/layers[/bearing]], got {} parts",
+            parts.len()
+        )));
+    }
+    let number = |name: &str, text: &str| match text.parse::<f64>() {
+        Ok(x) if x.is_finite() => Ok(x),
+        _ => Err(StateParseError::FormatError(format!(
+            "bad {name}: {text:?}"
+        ))),
+    };
+    let zoom = number("zoom", parts[0])?.clamp(MIN_Z as f64, MAX_Z as f64);
+    let lat = number("latitude", parts[1])?;
+    let lon = number("longitude", parts[2])?;
+
+    let mut state = MapState {
+        is_init: true,
+        zoom,
+        pos: lat_lon_to_world((lat, lon)),
+        ..INIT_STATE
+    };
+    if let Some(layers) = parts.get(3) {
+        let mut layers = layers.split('+');
+        let base = layers.next().unwrap_or_default();
+        if base.is_empty() {
+            return Err(StateParseError::FormatError("empty base layer".to_string()));
+        }
+        state.base_layer = Cow::Owned(base.to_string());
+        for overlay in layers {
+            let (id, opacity) = match overlay.split_once('@') {
+                Some((id, opacity)) => (id, number("opacity", opacity)?.clamp(0.0, 1.0)),
+                None => (overlay, 1.0),
+            };
+            if id.is_empty() {
+                return Err(StateParseError::FormatError("empty overlay id".to_string()));
+            }
+            state.overlays.push(OverlayLayer {
+                id: id.to_string(),
+                opacity,
+            });
+        }
+    }
+    if let Some(bearing) = parts.get(4) {
+        state.bearing = number("bearing", bearing)?.rem_euclid(360.0);
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<MapState> {
+        s.parse::<MapState>().ok()
+    }
+
+    #[test]
+    fn readable_round_trip() {
+        let state = MapState {
+            zoom: 9.25,
+            pos: lat_lon_to_world((45.5, 25.25)),
+            base_layer: Cow::Borrowed("osm"),
+            overlays: vec![
+                OverlayLayer {
+                    id: "labels".into(),
+                    opacity: 0.5,
+                },
+                OverlayLayer {
+                    id: "hillshade".into(),
+                    opacity: 1.0,
+                },
+            ],
+            bearing: 33.5,
+            ..INIT_STATE
+        };
+        let text = state.to_string();
+        assert_eq!(
+            text,
+            "9.25/45.5000000/25.2500000/osm+labels@0.50+hillshade@1.00/33.5"
+        );
+        let parsed = parse(&text).unwrap();
+        assert!((parsed.pos.0 - state.pos.0).abs() < 1e-3);
+        assert!((parsed.pos.1 - state.pos.1).abs() < 1e-3);
+        assert_eq!(
+            MapState {
+                pos: state.pos,
+                ..parsed
+            },
+            state
+        );
+        assert_eq!(parse(&text).unwrap().to_string(), text);
+    }
+
+    #[test]
+    fn default_layers_are_left_out() {
+        let text = INIT_STATE.to_string();
+        assert_eq!(text.split('/').count(), 3);
+        let parsed = parse(&text).unwrap();
+        assert_eq!(parsed.base_layer, DEFAULT_TILE_PROVIDER);
+        assert!(parsed.overlays.is_empty());
+        assert_eq!(parsed.bearing, 0.0);
+        assert_eq!(parsed.to_string(), text);
+    }
+
+    #[test]
+    fn overlays_without_opacity_are_opaque() {
+        let parsed = parse("3/10/20/osm+labels").unwrap();
+        assert_eq!(parsed.overlays[0].opacity, 1.0);
+        let parsed = parse("3/10/20/osm+labels@7").unwrap();
+        assert_eq!(parsed.overlays[0].opacity, 1.0);
+    }
+
+    #[test]
+    fn legacy_cbor_hash() {
+        // written by the app before the readable format: `{is_init, zoom: 12.5, pos}`
+        let parsed = parse("o2dpc19pbml09WR6b29t-UpAY3Bvc4L6SBJ8APpHuYwA").unwrap();
+        assert!(parsed.is_init);
+        assert_eq!(parsed.zoom, 12.5);
+        assert_eq!(parsed.pos, (150000.0, 95000.0));
+        assert_eq!(parsed.base_layer, DEFAULT_TILE_PROVIDER);
+        assert!(parsed.overlays.is_empty());
+        assert_eq!(parsed.bearing, 0.0);
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        for text in [
+            "",
+            "!!!",
+            "aGVsbG8=",
+            "1/2",
+            "1/2/3/4/5/6",
+            "abc/45/25",
+            "14/NaN/25",
+            "14/45/inf",
+            "14/45/25//",
+            "14/45/25/osm+@0.5",
+            "14/45/25/osm+labels@x",
+            "14/45/25/osm/north",
+        ] {
+            assert!(parse(text).is_none(), "{text:?} parsed");
+        }
+    }
+}